@@ -0,0 +1,429 @@
+//! The `payment-shop` command line tool: turns subcommands into contract calls and
+//! either prints them as unsigned JSON or signs them with a local keyfile.
+
+use clap::{Args, Parser, Subcommand};
+use near_crypto::{InMemorySigner, PublicKey, SecretKey};
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::serde_json::{self, json, Value};
+use near_sdk::Balance;
+use payment_shop_client::{FunctionCall, PaymentShopClient, ViewCall};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+#[derive(Parser, Debug)]
+#[clap(name = "payment-shop", about = "Build and sign payment shop contract calls")]
+pub struct Cli {
+    /// Account of the payment shop contract.
+    #[clap(long)]
+    pub contract: String,
+
+    #[clap(flatten)]
+    pub signing: Signing,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+/// Without a keyfile, calls are printed as unsigned JSON. The three options are only
+/// accepted together.
+#[derive(Args, Debug)]
+pub struct Signing {
+    /// Keyfile with `account_id`, `public_key` and `secret_key` or `private_key`, as written by near-cli.
+    #[clap(long, requires_all = &["nonce", "block-hash"])]
+    pub keyfile: Option<PathBuf>,
+
+    /// Nonce of the access key, one more than the last one used.
+    #[clap(long, requires = "keyfile")]
+    pub nonce: Option<u64>,
+
+    /// Base58 hash of a recent block.
+    #[clap(long, requires = "keyfile")]
+    pub block_hash: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Request a payment from a user, or an open one anyone can pay.
+    Request {
+        #[clap(long)]
+        order_id: u128,
+        #[clap(long, parse(try_from_str = parse_amount))]
+        fee: Balance,
+        #[clap(long)]
+        user: Option<String>,
+        #[clap(long, default_value = "")]
+        msg: String,
+        /// Nanoseconds after which an undisputed payment confirms itself.
+        #[clap(long)]
+        auto_confirm_after: Option<u64>,
+    },
+    /// Pay a requested payment.
+    Pay {
+        pay_id: u128,
+        #[clap(long, parse(try_from_str = parse_amount))]
+        fee: Balance,
+        #[clap(long, parse(try_from_str = parse_amount), default_value = "0")]
+        tip: Balance,
+        /// Pay a payment requested from another account.
+        #[clap(long = "for")]
+        on_behalf: bool,
+    },
+    /// Confirm a paid payment as its user.
+    Confirm { pay_id: u128 },
+    /// Claim confirmed payments as their shop.
+    Claim {
+        #[clap(required_unless_present = "all")]
+        pay_ids: Vec<u128>,
+        /// Claim the claimable payments among `--limit` open payments of the shop.
+        #[clap(long, conflicts_with = "pay_ids")]
+        all: bool,
+        #[clap(long, default_value = "50")]
+        limit: u64,
+        /// Where `--all` starts in the shop's open payments.
+        #[clap(long, requires = "all")]
+        from_index: Option<u64>,
+    },
+    /// Withdraw the platform fees as the owner, while no approvers are set.
+    Withdraw,
+    /// Propose sending platform fees to `receiver` as an approver.
+    ProposeWithdrawal {
+        #[clap(long, parse(try_from_str = parse_amount))]
+        amount: Balance,
+        #[clap(long)]
+        receiver: String,
+    },
+    /// Approve a proposal, which executes it once enough approvers have.
+    Approve { proposal_id: u64 },
+    /// Cancel a proposal as its proposer or the owner, or an expired one.
+    CancelProposal { proposal_id: u64 },
+    /// Set the platform fee, or the tip fee with `--tip`, in 1/100000 units.
+    SetFee {
+        percent: u128,
+        #[clap(long)]
+        tip: bool,
+    },
+    /// Print the view query for a payment, or for its history with `--history`.
+    Show {
+        pay_id: u128,
+        #[clap(long)]
+        history: bool,
+    },
+}
+
+/// What a subcommand turns into.
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    Call(FunctionCall),
+    View { account_id: String, method_name: String, args: Vec<u8> },
+}
+
+impl<T> From<ViewCall<T>> for Output {
+    fn from(view: ViewCall<T>) -> Self {
+        Output::View { account_id: view.receiver_id, method_name: view.method_name, args: view.args }
+    }
+}
+
+impl Command {
+    pub fn output(&self, client: &PaymentShopClient) -> Output {
+        let call = match self {
+            Command::Request { order_id, fee, user, msg, auto_confirm_after } => {
+                let mut request = client.req_payment(*order_id, *fee).msg(msg);
+                if let Some(user) = user {
+                    request = request.user(user);
+                }
+                if let Some(auto_confirm_after) = auto_confirm_after {
+                    request = request.auto_confirm_after(*auto_confirm_after);
+                }
+                request.build()
+            }
+            Command::Pay { pay_id, fee, tip, on_behalf } => {
+                let pay = if *on_behalf { client.pay_for(*pay_id, *fee) } else { client.pay(*pay_id, *fee) };
+                pay.tip(*tip).build()
+            }
+            Command::Confirm { pay_id } => client.confirm(*pay_id),
+            Command::Claim { all: true, limit, from_index, .. } => client.claim_all(*limit, *from_index),
+            Command::Claim { pay_ids, .. } if pay_ids.len() == 1 => client.claim(pay_ids[0]),
+            Command::Claim { pay_ids, .. } => client.claim_many(pay_ids),
+            Command::Withdraw => client.withdraw(),
+            Command::ProposeWithdrawal { amount, receiver } => client.propose_withdrawal(*amount, receiver),
+            Command::Approve { proposal_id } => client.approve_proposal(*proposal_id),
+            Command::CancelProposal { proposal_id } => client.cancel_proposal(*proposal_id),
+            Command::SetFee { percent, tip: true } => client.set_tip_fee(*percent),
+            Command::SetFee { percent, tip: false } => client.set_payment_fee(*percent),
+            Command::Show { pay_id, history: true } => return client.get_payment_history(*pay_id).into(),
+            Command::Show { pay_id, history: false } => return client.get_payment_info(*pay_id).into(),
+        };
+        Output::Call(call)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    InvalidAmount(String),
+    InvalidBlockHash(String),
+    /// The keyfile can't be read, lacks a field, or its public key isn't that of its secret key.
+    InvalidKeyfile(String),
+    /// Views are not transactions and cannot be signed.
+    ViewNotSignable,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::InvalidAmount(amount) => write!(f, "invalid amount: {}", amount),
+            CliError::InvalidBlockHash(hash) => write!(f, "invalid block hash: {}", hash),
+            CliError::InvalidKeyfile(keyfile) => write!(f, "invalid keyfile: {}", keyfile),
+            CliError::ViewNotSignable => write!(f, "show does not need a keyfile"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parses yoctoNEAR, or NEAR with an `N` or `NEAR` suffix, e.g. `1.5N`.
+pub fn parse_amount(amount: &str) -> Result<Balance, CliError> {
+    let invalid = || CliError::InvalidAmount(amount.to_string());
+    let near = amount.strip_suffix("NEAR").or_else(|| amount.strip_suffix('N'));
+    let near = match near {
+        Some(near) => near.trim(),
+        None => return amount.parse().map_err(|_| invalid()),
+    };
+
+    let (whole, fraction) = near.split_once('.').unwrap_or((near, ""));
+    if fraction.len() > 24 || (whole.is_empty() && fraction.is_empty()) {
+        return Err(invalid());
+    }
+    let whole: Balance = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+    let fraction: Balance = if fraction.is_empty() { 0 } else { format!("{:0<24}", fraction).parse().map_err(|_| invalid())? };
+
+    whole.checked_mul(NEAR).and_then(|whole| whole.checked_add(fraction)).ok_or_else(invalid)
+}
+
+/// The call as JSON for near-cli or an RPC client, or the `query` params of a view.
+pub fn unsigned_json(output: &Output) -> Value {
+    match output {
+        Output::Call(call) => json!({
+            "receiver_id": call.receiver_id,
+            "method_name": call.method_name,
+            "args": serde_json::from_slice::<Value>(&call.args).unwrap(),
+            "gas": call.gas,
+            "deposit": call.deposit.to_string()
+        }),
+        Output::View { account_id, method_name, args } => json!({
+            "request_type": "call_function",
+            "finality": "final",
+            "account_id": account_id,
+            "method_name": method_name,
+            "args_base64": base64::encode(args)
+        }),
+    }
+}
+
+/// Reads a near-cli keyfile, which names the secret key either `secret_key` or `private_key`.
+pub fn read_keyfile(keyfile: &Path) -> Result<InMemorySigner, CliError> {
+    let invalid = || CliError::InvalidKeyfile(keyfile.display().to_string());
+    let contents = fs::read_to_string(keyfile).map_err(|_| invalid())?;
+    let keys: Value = serde_json::from_str(&contents).map_err(|_| invalid())?;
+    let field = |name: &str| keys[name].as_str();
+
+    let account_id = field("account_id").ok_or_else(invalid)?;
+    let secret_key: SecretKey = field("secret_key")
+        .or_else(|| field("private_key"))
+        .and_then(|key| key.parse().ok())
+        .ok_or_else(invalid)?;
+    let public_key: PublicKey = field("public_key").and_then(|key| key.parse().ok()).ok_or_else(invalid)?;
+    if secret_key.public_key() != public_key {
+        return Err(invalid());
+    }
+
+    Ok(InMemorySigner { account_id: account_id.to_string(), public_key, secret_key })
+}
+
+/// Signs the call and returns the transaction base64 encoded, as `broadcast_tx_commit` takes it.
+pub fn sign(output: &Output, signer: &InMemorySigner, nonce: u64, block_hash: &str) -> Result<String, CliError> {
+    let call = match output {
+        Output::Call(call) => call,
+        Output::View { .. } => return Err(CliError::ViewNotSignable),
+    };
+    let block_hash = bs58::decode(block_hash)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| CryptoHash::try_from(bytes.as_slice()).ok())
+        .ok_or_else(|| CliError::InvalidBlockHash(block_hash.to_string()))?;
+
+    let transaction = Transaction {
+        signer_id: signer.account_id.clone(),
+        public_key: signer.public_key.clone(),
+        nonce,
+        receiver_id: call.receiver_id.clone(),
+        block_hash,
+        actions: vec![Action::FunctionCall(FunctionCallAction {
+            method_name: call.method_name.clone(),
+            args: call.args.clone(),
+            gas: call.gas,
+            deposit: call.deposit,
+        })],
+    };
+    let signed_transaction = transaction.sign(signer);
+
+    Ok(base64::encode(signed_transaction.try_to_vec().unwrap()))
+}
+
+/// Runs the command line and returns what to print.
+pub fn run(cli: &Cli) -> Result<String, CliError> {
+    let client = PaymentShopClient::new(cli.contract.clone());
+    let output = cli.command.output(&client);
+
+    match (&cli.signing.keyfile, cli.signing.nonce, &cli.signing.block_hash) {
+        (Some(keyfile), Some(nonce), Some(block_hash)) => {
+            let signer = read_keyfile(keyfile)?;
+            sign(&output, &signer, nonce, block_hash)
+        }
+        _ => Ok(serde_json::to_string_pretty(&unsigned_json(&output)).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_crypto::KeyType;
+    use near_primitives::hash::hash;
+    use near_primitives::transaction::SignedTransaction;
+    use near_sdk::borsh::BorshDeserialize;
+
+    fn output(args: &[&str]) -> Output {
+        let cli = Cli::try_parse_from([&["payment-shop", "--contract", "shop.near"][..], args].concat()).unwrap();
+        cli.command.output(&PaymentShopClient::new(cli.contract))
+    }
+
+    fn call(args: &[&str]) -> FunctionCall {
+        match output(args) {
+            Output::Call(call) => call,
+            Output::View { .. } => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("15"), Ok(15));
+        assert_eq!(parse_amount("1N"), Ok(NEAR));
+        assert_eq!(parse_amount("1.5NEAR"), Ok(NEAR + NEAR / 2));
+        assert_eq!(parse_amount(".000000000000000000000001N"), Ok(1));
+        assert!(parse_amount("0.0000000000000000000000001N").is_err());
+        assert!(parse_amount("N").is_err());
+        assert!(parse_amount("ten").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let request = call(&["request", "--order-id", "1", "--fee", "10N", "--user", "bob.near"]);
+        assert_eq!(request.method_name, "req_payment");
+
+        let pay = call(&["pay", "1", "--fee", "10N", "--tip", "1N"]);
+        assert_eq!((pay.method_name.as_str(), pay.deposit), ("pay", 11 * NEAR));
+        assert_eq!(call(&["pay", "1", "--fee", "10N", "--for"]).method_name, "pay_for");
+
+        assert_eq!(call(&["claim", "1"]).method_name, "claim");
+        assert_eq!(call(&["claim", "1", "2"]).method_name, "claim_many");
+        assert_eq!(call(&["claim", "--all"]).method_name, "claim_all");
+        let claim_all = call(&["claim", "--all", "--from-index", "5"]);
+        assert_eq!(serde_json::from_slice::<Value>(&claim_all.args).unwrap(), json!({ "limit": 50, "from_index": "5" }));
+        assert_eq!(call(&["set-fee", "20000"]).method_name, "set_payment_fee");
+        assert_eq!(call(&["set-fee", "5000", "--tip"]).method_name, "set_tip_fee");
+        assert_eq!(call(&["withdraw"]).deposit, 1);
+
+        let propose = call(&["propose-withdrawal", "--amount", "2N", "--receiver", "treasury.near"]);
+        assert_eq!(propose.method_name, "propose_withdrawal");
+        assert_eq!(serde_json::from_slice::<Value>(&propose.args).unwrap(), json!({ "amount": (2 * NEAR).to_string(), "receiver": "treasury.near" }));
+        assert_eq!(call(&["approve", "1"]).method_name, "approve_proposal");
+        assert_eq!(call(&["cancel-proposal", "1"]).method_name, "cancel_proposal");
+    }
+
+    #[test]
+    fn test_show() {
+        let output = output(&["show", "3", "--history"]);
+        assert_eq!(unsigned_json(&output)["method_name"], "get_payment_history");
+        assert_eq!(sign(&output, &InMemorySigner::from_seed("ops.near", near_crypto::KeyType::ED25519, "ops.near"), 1, ""), Err(CliError::ViewNotSignable));
+    }
+
+    #[test]
+    fn test_signing_options_go_together() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["payment-shop", "--contract", "shop.near"][..], args, &["confirm", "1"]].concat());
+        let block_hash = bs58::encode([7u8; 32]).into_string();
+
+        assert!(parse(&["--keyfile", "key.json", "--nonce", "5", "--block-hash", &block_hash]).is_ok());
+        assert!(parse(&["--keyfile", "key.json", "--nonce", "5"]).is_err());
+        assert!(parse(&["--nonce", "5"]).is_err());
+        assert!(parse(&["--block-hash", &block_hash]).is_err());
+        assert!(parse(&["--nonce", "5", "--block-hash", &block_hash]).is_err());
+    }
+
+    #[test]
+    fn test_sign() {
+        let signer = InMemorySigner::from_seed("ops.near", KeyType::ED25519, "ops.near");
+        let block_hash = bs58::encode([7u8; 32]).into_string();
+
+        let signed = sign(&output(&["confirm", "1"]), &signer, 5, &block_hash).unwrap();
+        let signed = SignedTransaction::try_from_slice(&base64::decode(signed).unwrap()).unwrap();
+        let transaction = &signed.transaction;
+        let transaction_hash = hash(&transaction.try_to_vec().unwrap());
+        assert!(signed.signature.verify(transaction_hash.as_ref(), &signer.public_key));
+        let other = InMemorySigner::from_seed("ops.near", KeyType::ED25519, "other");
+        assert!(!signed.signature.verify(transaction_hash.as_ref(), &other.public_key));
+
+        let confirm = call(&["confirm", "1"]);
+        assert_eq!(transaction.signer_id, "ops.near");
+        assert_eq!(transaction.public_key, signer.public_key);
+        assert_eq!(transaction.receiver_id, "shop.near");
+        assert_eq!(transaction.nonce, 5);
+        assert_eq!(transaction.block_hash, CryptoHash::try_from(&[7u8; 32][..]).unwrap());
+        assert_eq!(transaction.actions, vec![Action::FunctionCall(FunctionCallAction {
+            method_name: confirm.method_name,
+            args: confirm.args,
+            gas: confirm.gas,
+            deposit: confirm.deposit,
+        })]);
+        assert_eq!(sign(&output(&["confirm", "1"]), &signer, 5, "not-a-hash"), Err(CliError::InvalidBlockHash("not-a-hash".to_string())));
+    }
+
+    #[test]
+    fn test_read_keyfile() {
+        let signer = InMemorySigner::from_seed("ops.near", KeyType::ED25519, "ops.near");
+        let other = InMemorySigner::from_seed("ops.near", KeyType::ED25519, "other");
+        let dir = std::env::temp_dir().join(format!("payment-shop-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: String| {
+            let keyfile = dir.join(name);
+            fs::write(&keyfile, contents).unwrap();
+            keyfile
+        };
+
+        let keyfile = write("near-cli.json", json!({
+            "account_id": "ops.near",
+            "public_key": signer.public_key.to_string(),
+            "private_key": signer.secret_key.to_string()
+        }).to_string());
+        let read = read_keyfile(&keyfile).unwrap();
+        assert_eq!(read.account_id, "ops.near");
+        assert_eq!(read.public_key, signer.public_key);
+        assert_eq!(read.secret_key.to_string(), signer.secret_key.to_string());
+
+        let mismatched = write("mismatched.json", json!({
+            "account_id": "ops.near",
+            "public_key": other.public_key.to_string(),
+            "secret_key": signer.secret_key.to_string()
+        }).to_string());
+        let no_secret = write("no-secret.json", json!({ "account_id": "ops.near", "public_key": signer.public_key.to_string() }).to_string());
+        let not_json = write("not-json.json", "ops.near".to_string());
+        for keyfile in [mismatched, no_secret, not_json, dir.join("missing.json")] {
+            assert_eq!(read_keyfile(&keyfile).err(), Some(CliError::InvalidKeyfile(keyfile.display().to_string())));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+use clap::Parser;
+use payment_shop_cli::{run, Cli};
+
+fn main() {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
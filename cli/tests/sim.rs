@@ -0,0 +1,107 @@
+use clap::Parser;
+use near_crypto::{InMemorySigner, KeyType};
+use near_primitives::hash::hash;
+use near_primitives::transaction::{Action, SignedTransaction};
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::serde_json::json;
+use near_sdk_sim::{init_simulator, to_yocto, UserAccount, STORAGE_AMOUNT, DEFAULT_GAS};
+use payment_shop_cli::{Cli, Output};
+use payment_shop_client::{PaymentJson, PaymentShopClient, Status};
+
+#[path = "../../tests/common/contract.rs"]
+mod contract;
+
+const PAYMENT_SHOP_CONTRACT_ID: &str = "payment_shop_contract";
+
+/// Runs a `payment-shop` command line as `account`.
+fn run(account: &UserAccount, args: &[&str]) -> Output {
+    let cli = Cli::try_parse_from([&["payment-shop", "--contract", PAYMENT_SHOP_CONTRACT_ID][..], args].concat()).unwrap();
+    let output = cli.command.output(&PaymentShopClient::new(cli.contract));
+
+    if let Output::Call(call) = &output {
+        account.call(call.receiver_id.clone(), &call.method_name, &call.args, call.gas, call.deposit).assert_success();
+    }
+    output
+}
+
+/// Signs a `payment-shop` command line with a keyfile for `account` and checks the signed
+/// transaction: its signature, header and the call it carries.
+///
+/// The signed bytes are not submitted. The simulator only runs transactions it signs
+/// itself, with its own key for `account`, so the decoded call is run through
+/// `account.call` instead.
+fn run_signed(account: &UserAccount, args: &[&str]) {
+    let signer = InMemorySigner::from_seed(&account.account_id(), KeyType::ED25519, "cli");
+    let keyfile = std::env::temp_dir().join(format!("payment-shop-cli-sim-{}.json", std::process::id()));
+    std::fs::write(&keyfile, json!({
+        "account_id": account.account_id(),
+        "public_key": signer.public_key.to_string(),
+        "private_key": signer.secret_key.to_string()
+    }).to_string()).unwrap();
+    let block_hash = bs58::encode([3u8; 32]).into_string();
+    let signing = ["--keyfile", keyfile.to_str().unwrap(), "--nonce", "9", "--block-hash", &block_hash];
+    let cli = Cli::try_parse_from([&["payment-shop", "--contract", PAYMENT_SHOP_CONTRACT_ID][..], &signing, args].concat()).unwrap();
+    let signed = payment_shop_cli::run(&cli).unwrap();
+    std::fs::remove_file(&keyfile).unwrap();
+
+    let signed = SignedTransaction::try_from_slice(&base64::decode(signed).unwrap()).unwrap();
+    let transaction = &signed.transaction;
+    assert!(signed.signature.verify(hash(&transaction.try_to_vec().unwrap()).as_ref(), &signer.public_key));
+    assert_eq!(transaction.signer_id, account.account_id());
+    assert_eq!(transaction.receiver_id, PAYMENT_SHOP_CONTRACT_ID);
+    assert_eq!(transaction.nonce, 9);
+    assert_eq!(transaction.block_hash.as_ref(), &[3u8; 32][..]);
+
+    let expected = match cli.command.output(&PaymentShopClient::new(cli.contract.clone())) {
+        Output::Call(call) => call,
+        Output::View { .. } => unreachable!(),
+    };
+    match &transaction.actions[..] {
+        [Action::FunctionCall(call)] => {
+            assert_eq!((&call.method_name, &call.args, call.gas, call.deposit), (&expected.method_name, &expected.args, expected.gas, expected.deposit));
+            account.call(transaction.receiver_id.clone(), &call.method_name, &call.args, call.gas, call.deposit).assert_success();
+        }
+        actions => panic!("expected a single function call, got {:?}", actions),
+    }
+}
+
+fn show(account: &UserAccount, pay_id: &str) -> PaymentJson {
+    match run(account, &["show", pay_id]) {
+        Output::View { account_id, method_name, args } => account.view(account_id, &method_name, &args).unwrap_json(),
+        Output::Call(_) => unreachable!(),
+    }
+}
+
+#[test]
+pub fn test_cli_payment_flow() {
+    let root = init_simulator(None);
+    let alice = root.create_user("alice".to_string(), to_yocto("100"));
+    let bod = root.create_user("bod".to_string(), to_yocto("100"));
+
+    root.deploy_and_init(
+        contract::wasm(),
+        PAYMENT_SHOP_CONTRACT_ID.to_string(),
+        "new",
+        &json!({
+            "owner_id": root.account_id(),
+            "payment_fee_percent": "20000"
+        }).to_string().as_bytes(),
+        STORAGE_AMOUNT,
+        DEFAULT_GAS
+    );
+
+    run(&alice, &["request", "--order-id", "1", "--fee", "10N", "--user", "bod", "--msg", "Hello"]);
+    assert_eq!(show(&root, "1").status, Status::REQUESTING);
+
+    run(&bod, &["pay", "1", "--fee", "10N"]);
+    run_signed(&bod, &["confirm", "1"]);
+    assert_eq!(show(&root, "1").status, Status::CONFIRMED);
+
+    let alice_balance = alice.account().unwrap().amount;
+    run(&alice, &["claim", "1"]);
+    assert_eq!(show(&root, "1").status, Status::CLAIMED);
+    assert!(alice.account().unwrap().amount - alice_balance > to_yocto("7.9"));
+
+    run(&root, &["set-fee", "10000"]);
+    run(&root, &["withdraw"]);
+}
@@ -0,0 +1,368 @@
+use near_sdk::serde_json;
+use near_sdk::{AccountId, Balance};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::PaymentJson;
+
+/// A log line written by the payment shop contract.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    RequestPayment { payment_id: u128, order_id: u128, user: Option<AccountId>, fee: Balance, msg: String },
+    Pay {
+        payment_id: u128,
+        parent_id: Option<u128>,
+        payer: Option<AccountId>,
+        coupon: Option<String>,
+        discount: Balance,
+        tip: Balance,
+    },
+    PayMany { count: u64, amount: Balance, refund: Balance },
+    Confirm { payment_id: u128 },
+    AutoConfirm { payment_id: u128 },
+    Ship { payment_id: u128 },
+    Deliver { payment_id: u128 },
+    Dispute { payment_id: u128 },
+    /// `storage` is what the payment's status history cost to store, kept out of `amount`
+    /// until the payment is archived.
+    ShopClaim { payment_id: u128, amount: Balance, storage: Balance, tip_receiver: Option<AccountId>, tip: Balance },
+    ShopClaimMany { count: u64, amount: Balance },
+    /// `storage` is kept out of `amount` as for [`Event::ShopClaim`].
+    Refund { payment_id: u128, account: AccountId, amount: Balance, storage: Balance },
+    /// Last state of a payment removed by `archive_payments`.
+    ArchivePayment(Box<PaymentJson>),
+    /// `receiver` and `proposal_id` are only logged for withdrawals approved by the approvers.
+    Withdraw { amount: Balance, receiver: Option<AccountId>, proposal_id: Option<u64> },
+    /// The transfer of a withdrawal failed, its amount can be withdrawn again.
+    WithdrawFailed { amount: Balance, receiver: AccountId },
+    SetPaymentFee { payment_fee_percent: u128 },
+    SetTipFee { tip_fee_percent: u128 },
+    CreateCoupon { shop: AccountId, code: String, max_uses: u64 },
+    StorageDeposit { account: AccountId, amount: Balance },
+    StorageWithdraw { account: AccountId, amount: Balance },
+    SetApprovers { threshold: u64, approvers: Vec<AccountId> },
+    ProposeWithdrawal { proposal_id: u64, account: AccountId, amount: Balance, receiver: AccountId },
+    ProposeApprovers { proposal_id: u64, account: AccountId, threshold: u64, approvers: Vec<AccountId> },
+    ApproveProposal { proposal_id: u64, account: AccountId },
+    CancelProposal { proposal_id: u64, account: AccountId },
+    /// After an upgrade, `payments` of the first deployed version are counted in the stats
+    /// and `remaining` still wait for `migrate_batch`.
+    Migrate { payments: u128, remaining: u128 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EventParseError {
+    /// Not a log line of this contract.
+    UnknownEvent(String),
+    MissingField(&'static str),
+    InvalidNumber(String),
+    InvalidPayment(String),
+}
+
+impl fmt::Display for EventParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventParseError::UnknownEvent(log) => write!(f, "unknown event: {}", log),
+            EventParseError::MissingField(field) => write!(f, "missing field: {}", field),
+            EventParseError::InvalidNumber(value) => write!(f, "invalid number: {}", value),
+            EventParseError::InvalidPayment(err) => write!(f, "invalid payment: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EventParseError {}
+
+/// The "key: value" and "key value" pairs of a log line, which separates them with ", ".
+struct Fields<'a>(HashMap<&'a str, &'a str>);
+
+impl<'a> Fields<'a> {
+    fn parse(body: &'a str) -> Self {
+        Fields(body
+            .split(", ")
+            .filter_map(|field| field.split_once(": ").or_else(|| field.split_once(' ')))
+            .collect())
+    }
+
+    fn string(&self, key: &'static str) -> Result<String, EventParseError> {
+        self.0.get(key).map(|value| value.to_string()).ok_or(EventParseError::MissingField(key))
+    }
+
+    fn opt_string(&self, key: &'static str) -> Option<String> {
+        self.0.get(key).map(|value| value.to_string())
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &'static str) -> Result<T, EventParseError> {
+        let value = self.0.get(key).ok_or(EventParseError::MissingField(key))?;
+        value.parse().map_err(|_| EventParseError::InvalidNumber(value.to_string()))
+    }
+
+    /// A list of accounts separated by spaces.
+    fn accounts(&self, key: &'static str) -> Result<Vec<AccountId>, EventParseError> {
+        Ok(self.string(key)?.split(' ').map(|account| account.to_string()).collect())
+    }
+
+    fn opt_number<T: std::str::FromStr>(&self, key: &'static str) -> Result<Option<T>, EventParseError> {
+        if self.0.contains_key(key) {
+            self.number(key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Event {
+    pub fn parse(log: &str) -> Result<Event, EventParseError> {
+        let (name, body) = log.split_once(": ").ok_or_else(|| EventParseError::UnknownEvent(log.to_string()))?;
+
+        let event = match name {
+            "Request payment" => {
+                // the message is free text and comes last
+                let (body, msg) = body.split_once(", data: ").ok_or(EventParseError::MissingField("data"))?;
+                let fields = Fields::parse(body);
+                let user = fields.string("account")?;
+                Event::RequestPayment {
+                    payment_id: fields.number("payment_id")?,
+                    order_id: fields.number("order_id")?,
+                    user: if user.is_empty() { None } else { Some(user) },
+                    fee: fields.number("fee")?,
+                    msg: msg.to_string(),
+                }
+            }
+            "Pay" => {
+                let fields = Fields::parse(body);
+                Event::Pay {
+                    payment_id: fields.number("payment_id")?,
+                    parent_id: fields.opt_number("parent_id")?,
+                    payer: fields.opt_string("payer"),
+                    coupon: fields.opt_string("coupon"),
+                    discount: fields.opt_number("discount")?.unwrap_or(0),
+                    tip: fields.opt_number("tip")?.unwrap_or(0),
+                }
+            }
+            "Pay many" => {
+                let fields = Fields::parse(body);
+                Event::PayMany { count: fields.number("count")?, amount: fields.number("amount")?, refund: fields.number("refund")? }
+            }
+            "Confirm" => Event::Confirm { payment_id: Fields::parse(body).number("payment_id")? },
+            "Auto confirm" => Event::AutoConfirm { payment_id: Fields::parse(body).number("payment_id")? },
+            "Ship" => Event::Ship { payment_id: Fields::parse(body).number("payment_id")? },
+            "Deliver" => Event::Deliver { payment_id: Fields::parse(body).number("payment_id")? },
+            "Dispute" => Event::Dispute { payment_id: Fields::parse(body).number("payment_id")? },
+            "Shop claim" => {
+                let fields = Fields::parse(body);
+                Event::ShopClaim {
+                    payment_id: fields.number("payment_id")?,
+                    amount: fields.number("amount")?,
+                    storage: fields.opt_number("storage")?.unwrap_or(0),
+                    tip_receiver: fields.opt_string("tip_receiver"),
+                    tip: fields.opt_number("tip")?.unwrap_or(0),
+                }
+            }
+            "Shop claim many" => {
+                let fields = Fields::parse(body);
+                Event::ShopClaimMany { count: fields.number("count")?, amount: fields.number("amount")? }
+            }
+            "Refund" => {
+                let fields = Fields::parse(body);
+                Event::Refund {
+                    payment_id: fields.number("payment_id")?,
+                    account: fields.string("account")?,
+                    amount: fields.number("amount")?,
+                    storage: fields.opt_number("storage")?.unwrap_or(0),
+                }
+            }
+            "Archive payment" => {
+                let payment = serde_json::from_str(body).map_err(|err| EventParseError::InvalidPayment(err.to_string()))?;
+                Event::ArchivePayment(Box::new(payment))
+            }
+            "Withdraw" => {
+                let fields = Fields::parse(body);
+                Event::Withdraw {
+                    amount: fields.number("amount")?,
+                    receiver: fields.opt_string("receiver"),
+                    proposal_id: fields.opt_number("proposal_id")?,
+                }
+            }
+            "Withdraw failed" => {
+                let fields = Fields::parse(body);
+                Event::WithdrawFailed { amount: fields.number("amount")?, receiver: fields.string("receiver")? }
+            }
+            "Set payment fee" => Event::SetPaymentFee {
+                payment_fee_percent: body.parse().map_err(|_| EventParseError::InvalidNumber(body.to_string()))?,
+            },
+            "Set tip fee" => Event::SetTipFee {
+                tip_fee_percent: body.parse().map_err(|_| EventParseError::InvalidNumber(body.to_string()))?,
+            },
+            "Create coupon" => {
+                let fields = Fields::parse(body);
+                Event::CreateCoupon { shop: fields.string("shop")?, code: fields.string("code")?, max_uses: fields.number("max_uses")? }
+            }
+            "Storage deposit" => {
+                let fields = Fields::parse(body);
+                Event::StorageDeposit { account: fields.string("account")?, amount: fields.number("amount")? }
+            }
+            "Storage withdraw" => {
+                let fields = Fields::parse(body);
+                Event::StorageWithdraw { account: fields.string("account")?, amount: fields.number("amount")? }
+            }
+            "Set approvers" => {
+                let fields = Fields::parse(body);
+                Event::SetApprovers { threshold: fields.number("threshold")?, approvers: fields.accounts("approvers")? }
+            }
+            "Propose withdrawal" => {
+                let fields = Fields::parse(body);
+                Event::ProposeWithdrawal {
+                    proposal_id: fields.number("proposal_id")?,
+                    account: fields.string("account")?,
+                    amount: fields.number("amount")?,
+                    receiver: fields.string("receiver")?,
+                }
+            }
+            "Propose approvers" => {
+                let fields = Fields::parse(body);
+                Event::ProposeApprovers {
+                    proposal_id: fields.number("proposal_id")?,
+                    account: fields.string("account")?,
+                    threshold: fields.number("threshold")?,
+                    approvers: fields.accounts("approvers")?,
+                }
+            }
+            "Approve proposal" => {
+                let fields = Fields::parse(body);
+                Event::ApproveProposal { proposal_id: fields.number("proposal_id")?, account: fields.string("account")? }
+            }
+            "Cancel proposal" => {
+                let fields = Fields::parse(body);
+                Event::CancelProposal { proposal_id: fields.number("proposal_id")?, account: fields.string("account")? }
+            }
+            "Migrate" => {
+                let fields = Fields::parse(body);
+                Event::Migrate { payments: fields.number("payments")?, remaining: fields.number("remaining")? }
+            }
+            _ => return Err(EventParseError::UnknownEvent(log.to_string())),
+        };
+
+        Ok(event)
+    }
+
+    /// Parses the logs of a transaction, skipping lines that are not events of this contract.
+    pub fn parse_logs<S: AsRef<str>>(logs: &[S]) -> Vec<Event> {
+        logs.iter().filter_map(|log| Event::parse(log.as_ref()).ok()).collect()
+    }
+
+    /// The payment the event is about, if it is about a single one.
+    pub fn payment_id(&self) -> Option<u128> {
+        match self {
+            Event::RequestPayment { payment_id, .. }
+            | Event::Pay { payment_id, .. }
+            | Event::Confirm { payment_id }
+            | Event::AutoConfirm { payment_id }
+            | Event::Ship { payment_id }
+            | Event::Deliver { payment_id }
+            | Event::Dispute { payment_id }
+            | Event::ShopClaim { payment_id, .. }
+            | Event::Refund { payment_id, .. } => Some(*payment_id),
+            Event::ArchivePayment(payment) => Some(payment.payment_id.0),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_payment() {
+        let event = Event::parse("Request payment: payment_id: 1, order_id: 7, account: bob, fee: 10, data: Hello, world").unwrap();
+        assert_eq!(event, Event::RequestPayment { payment_id: 1, order_id: 7, user: Some("bob".to_string()), fee: 10, msg: "Hello, world".to_string() });
+
+        let event = Event::parse("Request payment: payment_id: 2, order_id: 8, account: , fee: 10, data: ").unwrap();
+        assert_eq!(event, Event::RequestPayment { payment_id: 2, order_id: 8, user: None, fee: 10, msg: String::new() });
+    }
+
+    #[test]
+    fn test_parse_pay() {
+        assert_eq!(Event::parse("Pay: payment_id: 1").unwrap(), Event::Pay {
+            payment_id: 1,
+            parent_id: None,
+            payer: None,
+            coupon: None,
+            discount: 0,
+            tip: 0,
+        });
+        assert_eq!(Event::parse("Pay: payment_id: 3, parent_id: 2, payer: carol, coupon: SUMMER, discount 5, tip 7").unwrap(), Event::Pay {
+            payment_id: 3,
+            parent_id: Some(2),
+            payer: Some("carol".to_string()),
+            coupon: Some("SUMMER".to_string()),
+            discount: 5,
+            tip: 7,
+        });
+    }
+
+    #[test]
+    fn test_parse_claims() {
+        assert_eq!(
+            Event::parse("Shop claim: payment_id: 1, amount 8, tip_receiver: dave, tip 2").unwrap(),
+            Event::ShopClaim { payment_id: 1, amount: 8, storage: 0, tip_receiver: Some("dave".to_string()), tip: 2 }
+        );
+        assert_eq!(
+            Event::parse("Shop claim: payment_id: 1, amount 7, storage 1").unwrap(),
+            Event::ShopClaim { payment_id: 1, amount: 7, storage: 1, tip_receiver: None, tip: 0 }
+        );
+        assert_eq!(Event::parse("Shop claim many: count: 2, amount 16").unwrap(), Event::ShopClaimMany { count: 2, amount: 16 });
+        assert_eq!(Event::parse("Auto confirm: payment_id: 4").unwrap().payment_id(), Some(4));
+    }
+
+    #[test]
+    fn test_parse_misc() {
+        assert_eq!(
+            Event::parse("Refund: payment_id: 1, account: bob, amount 9, storage 1").unwrap(),
+            Event::Refund { payment_id: 1, account: "bob".to_string(), amount: 9, storage: 1 }
+        );
+        assert_eq!(Event::parse("Withdraw: amount 2").unwrap(), Event::Withdraw { amount: 2, receiver: None, proposal_id: None });
+        assert_eq!(Event::parse("Set payment fee: 20000").unwrap(), Event::SetPaymentFee { payment_fee_percent: 20000 });
+        assert_eq!(Event::parse("Storage deposit: account: bob, amount 5").unwrap(), Event::StorageDeposit { account: "bob".to_string(), amount: 5 });
+    }
+
+    #[test]
+    fn test_parse_treasury() {
+        assert_eq!(
+            Event::parse("Set approvers: threshold: 2, approvers: alice bob carol").unwrap(),
+            Event::SetApprovers { threshold: 2, approvers: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()] }
+        );
+        assert_eq!(
+            Event::parse("Propose withdrawal: proposal_id: 1, account: alice, amount 5, receiver: treasury").unwrap(),
+            Event::ProposeWithdrawal { proposal_id: 1, account: "alice".to_string(), amount: 5, receiver: "treasury".to_string() }
+        );
+        assert_eq!(Event::parse("Approve proposal: proposal_id: 1, account: bob").unwrap(), Event::ApproveProposal { proposal_id: 1, account: "bob".to_string() });
+        assert_eq!(
+            Event::parse("Withdraw: amount 5, receiver: treasury, proposal_id: 1").unwrap(),
+            Event::Withdraw { amount: 5, receiver: Some("treasury".to_string()), proposal_id: Some(1) }
+        );
+        assert_eq!(
+            Event::parse("Withdraw failed: amount 5, receiver: treasury").unwrap(),
+            Event::WithdrawFailed { amount: 5, receiver: "treasury".to_string() }
+        );
+        assert_eq!(Event::parse("Migrate: payments: 100, remaining 20").unwrap(), Event::Migrate { payments: 100, remaining: 20 });
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Event::parse("Transfer 5 to bob"), Err(EventParseError::UnknownEvent("Transfer 5 to bob".to_string())));
+        assert_eq!(Event::parse("Withdraw: amount lots"), Err(EventParseError::InvalidNumber("lots".to_string())));
+        assert_eq!(Event::parse("Confirm: pay_id: 1"), Err(EventParseError::MissingField("payment_id")));
+        assert_eq!(Event::parse_logs(&["Confirm: payment_id: 1", "hello"]), vec![Event::Confirm { payment_id: 1 }]);
+    }
+
+    #[test]
+    fn test_abi_events_known() {
+        let abi: serde_json::Value = serde_json::from_str(include_str!("../../abi/payment_shop.json")).unwrap();
+        for event in abi["events"].as_array().unwrap() {
+            let name = event["name"].as_str().unwrap();
+            let err = Event::parse(&format!("{}: ", name)).err();
+            assert!(!matches!(err, Some(EventParseError::UnknownEvent(_))), "{}", name);
+        }
+    }
+}
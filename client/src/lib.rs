@@ -0,0 +1,483 @@
+//! Typed calls to the payment shop contract and a parser for the events it logs.
+//!
+//! Calls are built as plain [`FunctionCall`]s and [`ViewCall`]s, with the deposit each
+//! method expects already attached, so they can be sent with any signer or RPC client.
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::de::DeserializeOwned;
+use near_sdk::serde_json::{self, json, Value};
+use near_sdk::{AccountId, Balance, Gas};
+use std::marker::PhantomData;
+
+pub use payment_shop_rust::{
+    ApproversJson, BalanceBreakdownJson, ContractError, CouponJson, Discount, PaymentJson, PaymentMetadata,
+    PaymentRequest, PaymentShopJson, PaymentStatsJson, ProposalAction, ProposalJson, Status, StatusChangeJson,
+    StatusEscrowJson, StorageBalance,
+};
+
+/// The contract interface as JSON schema, also checked in as `abi/payment_shop.json`.
+#[cfg(feature = "abi")]
+pub use payment_shop_rust::abi;
+
+pub use crate::events::{Event, EventParseError};
+
+mod events;
+
+/// Attached to owner, shop and user actions that must be signed with a full access key.
+pub const ONE_YOCTO: Balance = 1;
+/// Attached to calls that store new data. Whatever storage does not use is refunded.
+pub const DEFAULT_STORAGE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000; // 0.01 NEAR
+pub const DEFAULT_GAS: Gas = 100_000_000_000_000; // 100 TGas
+
+/// A change method call, ready to be signed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionCall {
+    pub receiver_id: AccountId,
+    pub method_name: String,
+    /// JSON encoded arguments.
+    pub args: Vec<u8>,
+    pub gas: Gas,
+    pub deposit: Balance,
+}
+
+impl FunctionCall {
+    pub fn with_gas(mut self, gas: Gas) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    pub fn with_deposit(mut self, deposit: Balance) -> Self {
+        self.deposit = deposit;
+        self
+    }
+}
+
+/// A view method call whose result decodes as `T`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewCall<T> {
+    pub receiver_id: AccountId,
+    pub method_name: String,
+    /// JSON encoded arguments.
+    pub args: Vec<u8>,
+    result: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> ViewCall<T> {
+    /// Decodes the raw result of the view call.
+    pub fn parse(&self, result: &[u8]) -> serde_json::Result<T> {
+        serde_json::from_slice(result)
+    }
+}
+
+/// Builds calls to one deployed payment shop contract.
+#[derive(Clone, Debug)]
+pub struct PaymentShopClient {
+    contract_id: AccountId,
+}
+
+impl PaymentShopClient {
+    pub fn new(contract_id: AccountId) -> Self {
+        PaymentShopClient { contract_id }
+    }
+
+    pub fn contract_id(&self) -> &AccountId {
+        &self.contract_id
+    }
+
+    fn call(&self, method_name: &str, args: Value, deposit: Balance) -> FunctionCall {
+        FunctionCall {
+            receiver_id: self.contract_id.clone(),
+            method_name: method_name.to_string(),
+            args: args.to_string().into_bytes(),
+            gas: DEFAULT_GAS,
+            deposit,
+        }
+    }
+
+    fn view<T>(&self, method_name: &str, args: Value) -> ViewCall<T> {
+        ViewCall {
+            receiver_id: self.contract_id.clone(),
+            method_name: method_name.to_string(),
+            args: args.to_string().into_bytes(),
+            result: PhantomData,
+        }
+    }
+
+    pub fn req_payment(&self, order_id: u128, fee: Balance) -> ReqPayment {
+        ReqPayment {
+            client: self.clone(),
+            request: PaymentRequest {
+                order_id: U128(order_id),
+                user_id: None,
+                msg: String::new(),
+                fee: U128(fee),
+                max_uses: None,
+                metadata: None,
+                tip_receiver: None,
+                auto_confirm_after: None,
+            },
+            deposit: DEFAULT_STORAGE_DEPOSIT,
+        }
+    }
+
+    /// Attaches `DEFAULT_STORAGE_DEPOSIT` for each request.
+    pub fn req_payments(&self, requests: Vec<PaymentRequest>) -> FunctionCall {
+        let deposit = DEFAULT_STORAGE_DEPOSIT * requests.len() as Balance;
+        self.call("req_payments", json!({ "requests": requests }), deposit)
+    }
+
+    /// Pays `fee` for a payment requested from the signer.
+    pub fn pay(&self, pay_id: u128, fee: Balance) -> Pay {
+        Pay::new(self, "pay", pay_id, fee, None, 0)
+    }
+
+    /// Pays `fee` for a payment requested from another account. Covers storage for
+    /// recording the signer as payer by default.
+    pub fn pay_for(&self, pay_id: u128, fee: Balance) -> Pay {
+        Pay::new(self, "pay_for", pay_id, fee, None, DEFAULT_STORAGE_DEPOSIT)
+    }
+
+    /// Pays a payment with coupon `code`. `fee` is what is owed after the discount.
+    pub fn pay_with_coupon(&self, pay_id: u128, code: &str, fee: Balance) -> Pay {
+        Pay::new(self, "pay_with_coupon", pay_id, fee, Some(code.to_string()), DEFAULT_STORAGE_DEPOSIT)
+    }
+
+    /// Pays several payments at once, attaching `DEFAULT_STORAGE_DEPOSIT` for each on
+    /// top of `total_fee`. Whatever is not used is refunded.
+    pub fn pay_many(&self, pay_ids: &[u128], total_fee: Balance) -> FunctionCall {
+        let deposit = total_fee + DEFAULT_STORAGE_DEPOSIT * pay_ids.len() as Balance;
+        self.call("pay_many", json!({ "pay_ids": to_u128s(pay_ids) }), deposit)
+    }
+
+    pub fn confirm(&self, pay_id: u128) -> FunctionCall {
+        self.call("confirm", json!({ "pay_id": U128(pay_id) }), ONE_YOCTO)
+    }
+
+    pub fn mark_shipped(&self, pay_id: u128, tracking_ref: &str) -> FunctionCall {
+        self.call("mark_shipped", json!({ "pay_id": U128(pay_id), "tracking_ref": tracking_ref }), DEFAULT_STORAGE_DEPOSIT)
+    }
+
+    pub fn mark_delivered(&self, pay_id: u128) -> FunctionCall {
+        self.call("mark_delivered", json!({ "pay_id": U128(pay_id) }), ONE_YOCTO)
+    }
+
+    pub fn dispute(&self, pay_id: u128) -> FunctionCall {
+        self.call("dispute", json!({ "pay_id": U128(pay_id) }), ONE_YOCTO)
+    }
+
+    pub fn claim(&self, pay_id: u128) -> FunctionCall {
+        self.call("claim", json!({ "pay_id": U128(pay_id) }), ONE_YOCTO)
+    }
+
+    pub fn claim_many(&self, pay_ids: &[u128]) -> FunctionCall {
+        self.call("claim_many", json!({ "pay_ids": to_u128s(pay_ids) }), ONE_YOCTO)
+    }
+
+    /// Claims what is claimable among `limit` of the shop's open payments from `from_index`.
+    pub fn claim_all(&self, limit: u64, from_index: Option<u64>) -> FunctionCall {
+        self.call("claim_all", json!({ "limit": limit, "from_index": from_index.map(U64) }), ONE_YOCTO)
+    }
+
+    pub fn refund(&self, pay_id: u128) -> FunctionCall {
+        self.call("refund", json!({ "pay_id": U128(pay_id) }), ONE_YOCTO)
+    }
+
+    pub fn archive_payments(&self, pay_ids: &[u128]) -> FunctionCall {
+        self.call("archive_payments", json!({ "pay_ids": to_u128s(pay_ids) }), ONE_YOCTO)
+    }
+
+    pub fn withdraw(&self) -> FunctionCall {
+        self.call("withdraw", json!({}), ONE_YOCTO)
+    }
+
+    pub fn set_payment_fee(&self, payment_fee_percent: u128) -> FunctionCall {
+        self.call("set_payment_fee", json!({ "payment_fee_percent": U128(payment_fee_percent) }), ONE_YOCTO)
+    }
+
+    pub fn set_tip_fee(&self, tip_fee_percent: u128) -> FunctionCall {
+        self.call("set_tip_fee", json!({ "tip_fee_percent": U128(tip_fee_percent) }), ONE_YOCTO)
+    }
+
+    /// Counts `limit` more payments of the first deployed version after an upgrade. The
+    /// call returns whether the migration is complete.
+    pub fn migrate_batch(&self, limit: u64) -> FunctionCall {
+        self.call("migrate_batch", json!({ "limit": limit }), ONE_YOCTO)
+    }
+
+    pub fn set_approvers(&self, approvers: &[&str], threshold: u64) -> FunctionCall {
+        self.call("set_approvers", json!({ "approvers": approvers, "threshold": threshold }), ONE_YOCTO)
+    }
+
+    /// Proposes sending `amount` of the platform fees to `receiver`, approved by the signer.
+    pub fn propose_withdrawal(&self, amount: Balance, receiver: &str) -> FunctionCall {
+        self.call("propose_withdrawal", json!({ "amount": U128(amount), "receiver": receiver }), ONE_YOCTO)
+    }
+
+    pub fn propose_approvers(&self, approvers: &[&str], threshold: u64) -> FunctionCall {
+        self.call("propose_approvers", json!({ "approvers": approvers, "threshold": threshold }), ONE_YOCTO)
+    }
+
+    pub fn approve_proposal(&self, proposal_id: u64) -> FunctionCall {
+        self.call("approve_proposal", json!({ "proposal_id": U64(proposal_id) }), ONE_YOCTO)
+    }
+
+    pub fn cancel_proposal(&self, proposal_id: u64) -> FunctionCall {
+        self.call("cancel_proposal", json!({ "proposal_id": U64(proposal_id) }), ONE_YOCTO)
+    }
+
+    /// `code` may only use ASCII letters, digits, `_` and `-`.
+    pub fn create_coupon(&self, code: &str, discount: Discount, max_uses: u64, expires_at: Option<u64>, per_user_limit: Option<u64>) -> FunctionCall {
+        self.call("create_coupon", json!({
+            "code": code,
+            "discount": discount,
+            "max_uses": max_uses,
+            "expires_at": expires_at.map(U64),
+            "per_user_limit": per_user_limit
+        }), DEFAULT_STORAGE_DEPOSIT)
+    }
+
+    /// Deposits `amount` for storage of `account_id`, or of the signer.
+    pub fn storage_deposit(&self, account_id: Option<AccountId>, amount: Balance) -> FunctionCall {
+        self.call("storage_deposit", json!({ "account_id": account_id }), amount)
+    }
+
+    /// Withdraws `amount` of the signer's available storage balance, or all of it.
+    pub fn storage_withdraw(&self, amount: Option<Balance>) -> FunctionCall {
+        self.call("storage_withdraw", json!({ "amount": amount.map(U128) }), ONE_YOCTO)
+    }
+
+    pub fn get_payment_info(&self, pay_id: u128) -> ViewCall<Option<PaymentJson>> {
+        self.view("get_payment_info", json!({ "pay_id": U128(pay_id) }))
+    }
+
+    pub fn get_payment_history(&self, pay_id: u128) -> ViewCall<Option<Vec<StatusChangeJson>>> {
+        self.view("get_payment_history", json!({ "pay_id": U128(pay_id) }))
+    }
+
+    pub fn get_payment_shop_info(&self) -> ViewCall<PaymentShopJson> {
+        self.view("get_payment_shop_info", json!({}))
+    }
+
+    pub fn get_payid_from_orderid(&self, order_id: u128) -> ViewCall<Option<U128>> {
+        self.view("get_payid_from_orderid", json!({ "order_id": U128(order_id) }))
+    }
+
+    pub fn get_shop_stats(&self, shop: &str) -> ViewCall<PaymentStatsJson> {
+        self.view("get_shop_stats", json!({ "shop": shop }))
+    }
+
+    pub fn get_platform_stats(&self) -> ViewCall<PaymentStatsJson> {
+        self.view("get_platform_stats", json!({}))
+    }
+
+    pub fn get_balance_breakdown(&self) -> ViewCall<BalanceBreakdownJson> {
+        self.view("get_balance_breakdown", json!({}))
+    }
+
+    pub fn get_coupon(&self, shop: &str, code: &str) -> ViewCall<Option<CouponJson>> {
+        self.view("get_coupon", json!({ "shop": shop, "code": code }))
+    }
+
+    pub fn get_proposal(&self, proposal_id: u64) -> ViewCall<Option<ProposalJson>> {
+        self.view("get_proposal", json!({ "proposal_id": U64(proposal_id) }))
+    }
+
+    pub fn get_approvers(&self) -> ViewCall<ApproversJson> {
+        self.view("get_approvers", json!({}))
+    }
+
+    pub fn storage_balance_of(&self, account_id: &str) -> ViewCall<Option<StorageBalance>> {
+        self.view("storage_balance_of", json!({ "account_id": account_id }))
+    }
+}
+
+fn to_u128s(values: &[u128]) -> Vec<U128> {
+    values.iter().map(|value| U128(*value)).collect()
+}
+
+/// Builder for `req_payment`.
+#[derive(Debug)]
+pub struct ReqPayment {
+    client: PaymentShopClient,
+    request: PaymentRequest,
+    deposit: Balance,
+}
+
+impl ReqPayment {
+    pub fn user(mut self, user_id: &str) -> Self {
+        self.request.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn msg(mut self, msg: &str) -> Self {
+        self.request.msg = msg.to_string();
+        self
+    }
+
+    /// Makes an open request a reusable payment link.
+    pub fn max_uses(mut self, max_uses: u64) -> Self {
+        self.request.max_uses = Some(max_uses);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: PaymentMetadata) -> Self {
+        self.request.metadata = Some(metadata);
+        self
+    }
+
+    pub fn tip_receiver(mut self, tip_receiver: &str) -> Self {
+        self.request.tip_receiver = Some(tip_receiver.to_string());
+        self
+    }
+
+    /// Nanoseconds after which an undisputed payment confirms itself.
+    pub fn auto_confirm_after(mut self, auto_confirm_after: u64) -> Self {
+        self.request.auto_confirm_after = Some(U64(auto_confirm_after));
+        self
+    }
+
+    pub fn storage_deposit(mut self, deposit: Balance) -> Self {
+        self.deposit = deposit;
+        self
+    }
+
+    pub fn build(self) -> FunctionCall {
+        let request = self.request;
+        self.client.call("req_payment", json!({
+            "order_id": request.order_id,
+            "user_id": request.user_id,
+            "msg": request.msg,
+            "fee": request.fee,
+            "max_uses": request.max_uses,
+            "metadata": request.metadata,
+            "tip_receiver": request.tip_receiver,
+            "auto_confirm_after": request.auto_confirm_after
+        }), self.deposit)
+    }
+}
+
+/// Builder for `pay`, `pay_for` and `pay_with_coupon`. The contract refunds whatever is
+/// attached beyond the fee, tip and storage.
+#[derive(Debug)]
+pub struct Pay {
+    client: PaymentShopClient,
+    method_name: &'static str,
+    pay_id: u128,
+    fee: Balance,
+    code: Option<String>,
+    tip: Balance,
+    storage_deposit: Balance,
+}
+
+impl Pay {
+    fn new(client: &PaymentShopClient, method_name: &'static str, pay_id: u128, fee: Balance, code: Option<String>, storage_deposit: Balance) -> Self {
+        Pay {
+            client: client.clone(),
+            method_name,
+            pay_id,
+            fee,
+            code,
+            tip: 0,
+            storage_deposit,
+        }
+    }
+
+    pub fn tip(mut self, tip: Balance) -> Self {
+        self.tip = tip;
+        self
+    }
+
+    /// Storage to cover on top of the fee, e.g. when paying an open request or payment link.
+    pub fn storage_deposit(mut self, storage_deposit: Balance) -> Self {
+        self.storage_deposit = storage_deposit;
+        self
+    }
+
+    pub fn build(self) -> FunctionCall {
+        let mut args = json!({ "pay_id": U128(self.pay_id) });
+        if let Some(code) = self.code {
+            args["code"] = json!(code);
+        }
+        if self.tip > 0 {
+            args["tip"] = json!(U128(self.tip));
+        }
+        self.client.call(self.method_name, args, self.fee + self.tip + self.storage_deposit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> PaymentShopClient {
+        PaymentShopClient::new("payment_shop_contract".to_string())
+    }
+
+    fn args(call: &FunctionCall) -> Value {
+        serde_json::from_slice(&call.args).unwrap()
+    }
+
+    #[test]
+    fn test_req_payment() {
+        let call = client().req_payment(1, 10).user("bob").msg("Hello").auto_confirm_after(1000).build();
+
+        assert_eq!(call.method_name, "req_payment");
+        assert_eq!(call.deposit, DEFAULT_STORAGE_DEPOSIT);
+        assert_eq!(args(&call), json!({
+            "order_id": "1",
+            "user_id": "bob",
+            "msg": "Hello",
+            "fee": "10",
+            "max_uses": null,
+            "metadata": null,
+            "tip_receiver": null,
+            "auto_confirm_after": "1000"
+        }));
+    }
+
+    #[test]
+    fn test_pay_deposits() {
+        let call = client().pay(1, 10).tip(2).build();
+        assert_eq!((call.method_name.as_str(), call.deposit), ("pay", 12));
+        assert_eq!(args(&call), json!({ "pay_id": "1", "tip": "2" }));
+
+        let call = client().pay_for(1, 10).build();
+        assert_eq!((call.method_name.as_str(), call.deposit), ("pay_for", 10 + DEFAULT_STORAGE_DEPOSIT));
+
+        let call = client().pay_with_coupon(1, "SUMMER", 8).storage_deposit(0).build();
+        assert_eq!((call.method_name.as_str(), call.deposit), ("pay_with_coupon", 8));
+        assert_eq!(args(&call), json!({ "pay_id": "1", "code": "SUMMER" }));
+
+        let call = client().pay_many(&[1, 2], 20);
+        assert_eq!((call.method_name.as_str(), call.deposit), ("pay_many", 20 + 2 * DEFAULT_STORAGE_DEPOSIT));
+        assert_eq!(args(&call), json!({ "pay_ids": ["1", "2"] }));
+    }
+
+    #[test]
+    fn test_one_yocto_calls() {
+        let client = client();
+        for call in [client.confirm(1), client.claim(1), client.claim_many(&[1, 2]), client.refund(1), client.withdraw(), client.set_payment_fee(1000), client.migrate_batch(100)] {
+            assert_eq!(call.deposit, ONE_YOCTO, "{}", call.method_name);
+        }
+        assert_eq!(args(&client.claim_many(&[1, 2])), json!({ "pay_ids": ["1", "2"] }));
+    }
+
+    #[test]
+    fn test_treasury_calls() {
+        let call = client().propose_withdrawal(5, "treasury");
+        assert_eq!((call.method_name.as_str(), call.deposit), ("propose_withdrawal", ONE_YOCTO));
+        assert_eq!(args(&call), json!({ "amount": "5", "receiver": "treasury" }));
+
+        assert_eq!(args(&client().set_approvers(&["alice", "bob"], 2)), json!({ "approvers": ["alice", "bob"], "threshold": 2 }));
+        assert_eq!(args(&client().approve_proposal(1)), json!({ "proposal_id": "1" }));
+    }
+
+    #[test]
+    fn test_view_parse() {
+        let view = client().get_payid_from_orderid(7);
+
+        assert_eq!(view.method_name, "get_payid_from_orderid");
+        assert_eq!(view.parse(b"\"3\"").unwrap(), Some(U128(3)));
+        assert_eq!(view.parse(b"null").unwrap(), None);
+    }
+}
@@ -0,0 +1,50 @@
+use near_sdk::serde_json::json;
+use near_sdk_sim::{init_simulator, to_yocto, UserAccount, STORAGE_AMOUNT, DEFAULT_GAS};
+use payment_shop_client::{FunctionCall, PaymentJson, PaymentShopClient, Status};
+
+#[path = "../../tests/common/contract.rs"]
+mod contract;
+
+const PAYMENT_SHOP_CONTRACT_ID: &str = "payment_shop_contract";
+
+fn call(account: &UserAccount, call: FunctionCall) {
+    account.call(call.receiver_id, &call.method_name, &call.args, call.gas, call.deposit).assert_success();
+}
+
+fn payment(account: &UserAccount, client: &PaymentShopClient, pay_id: u128) -> PaymentJson {
+    let view = client.get_payment_info(pay_id);
+    view.parse(&account.view(view.receiver_id.clone(), &view.method_name, &view.args).unwrap()).unwrap().unwrap()
+}
+
+#[test]
+pub fn test_client_pay_many() {
+    let root = init_simulator(None);
+    let alice = root.create_user("alice".to_string(), to_yocto("100"));
+    let bod = root.create_user("bod".to_string(), to_yocto("100"));
+
+    root.deploy_and_init(
+        contract::wasm(),
+        PAYMENT_SHOP_CONTRACT_ID.to_string(),
+        "new",
+        &json!({
+            "owner_id": root.account_id(),
+            "payment_fee_percent": "20000"
+        }).to_string().as_bytes(),
+        STORAGE_AMOUNT,
+        DEFAULT_GAS
+    );
+
+    // open requests take storage to record who paid them
+    let client = PaymentShopClient::new(PAYMENT_SHOP_CONTRACT_ID.to_string());
+    call(&alice, client.req_payment(1, to_yocto("10")).build());
+    call(&alice, client.req_payment(2, to_yocto("5")).build());
+    call(&bod, client.pay_many(&[1, 2], to_yocto("15")));
+
+    for pay_id in 1..=2 {
+        let payment = payment(&root, &client, pay_id);
+        assert_eq!((payment.status, payment.user), (Status::PAID, Some(bod.account_id())));
+    }
+    // what the storage deposit did not need is refunded
+    let bod_balance = bod.account().unwrap().amount;
+    assert!(bod_balance > to_yocto("84") && bod_balance < to_yocto("85"));
+}
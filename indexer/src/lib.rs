@@ -0,0 +1,833 @@
+//! Rebuilds payment shop state from the contract's logs into SQLite, since the
+//! contract keeps its payments in a map it cannot list.
+//!
+//! Outcomes come from any source, e.g. a [`JsonLinesSource`] export or a local
+//! simulator, and are applied once each. [`Indexer::reconcile`] then checks the
+//! projection against `get_payment_info`, and [`Indexer::daily_report`] sums the
+//! funds moved per day and shop, to be checked against `get_balance_breakdown`.
+
+use near_sdk::serde_json::{self, Value};
+use near_sdk::{AccountId, Balance};
+use payment_shop_client::{BalanceBreakdownJson, Event, EventParseError, PaymentJson, PaymentShopJson, Status};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql};
+use std::fmt;
+use std::path::Path;
+
+pub use crate::report::{write_csv, DailyShopReport, CSV_HEADER};
+pub use crate::source::{ExecutionOutcome, JsonLinesSource};
+
+use crate::report::Entry;
+
+mod report;
+mod source;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS receipts (
+    receipt_id TEXT PRIMARY KEY,
+    block_height INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS payments (
+    payment_id TEXT PRIMARY KEY,
+    order_id TEXT NOT NULL,
+    shop TEXT NOT NULL,
+    user_id TEXT,
+    payer TEXT,
+    parent_id TEXT,
+    msg TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    discount TEXT NOT NULL,
+    tip TEXT NOT NULL,
+    coupon TEXT,
+    status TEXT NOT NULL,
+    uses INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS payments_shop ON payments (shop);
+CREATE TABLE IF NOT EXISTS shops (
+    shop TEXT PRIMARY KEY,
+    payments INTEGER NOT NULL,
+    claimed TEXT NOT NULL,
+    refunded TEXT NOT NULL,
+    fees TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fees (
+    payment_id TEXT PRIMARY KEY,
+    shop TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    block_height INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS withdrawals (
+    receipt_id TEXT PRIMARY KEY,
+    amount TEXT NOT NULL,
+    block_height INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS ledger (
+    receipt_id TEXT NOT NULL,
+    block_timestamp INTEGER NOT NULL,
+    shop TEXT NOT NULL,
+    payment_id TEXT,
+    kind TEXT NOT NULL,
+    amount TEXT NOT NULL
+);
+";
+
+const PAYMENT_COLUMNS: &str =
+    "payment_id, order_id, shop, user_id, payer, parent_id, msg, fee, discount, tip, coupon, status, uses, updated_at";
+
+#[derive(Debug)]
+pub enum IndexerError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    /// A log line of a known event that could not be parsed.
+    Event { receipt_id: String, error: EventParseError },
+    /// An event about a payment that was never requested, e.g. when indexing started late.
+    UnknownPayment(u128),
+    /// A claim paid out more than the payment held.
+    InvalidClaim(u128),
+    /// A ledger row of a kind the indexer never records, e.g. written through [`Indexer::connection`].
+    UnknownLedgerKind(String),
+}
+
+impl fmt::Display for IndexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexerError::Io(err) => write!(f, "io: {}", err),
+            IndexerError::Json(err) => write!(f, "invalid outcome: {}", err),
+            IndexerError::Sqlite(err) => write!(f, "sqlite: {}", err),
+            IndexerError::Event { receipt_id, error } => write!(f, "receipt {}: {}", receipt_id, error),
+            IndexerError::UnknownPayment(payment_id) => write!(f, "unknown payment: {}", payment_id),
+            IndexerError::InvalidClaim(payment_id) => write!(f, "claim exceeds payment: {}", payment_id),
+            IndexerError::UnknownLedgerKind(kind) => write!(f, "unknown ledger kind: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for IndexerError {}
+
+impl From<std::io::Error> for IndexerError {
+    fn from(err: std::io::Error) -> Self {
+        IndexerError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for IndexerError {
+    fn from(err: serde_json::Error) -> Self {
+        IndexerError::Json(err)
+    }
+}
+
+impl From<rusqlite::Error> for IndexerError {
+    fn from(err: rusqlite::Error) -> Self {
+        IndexerError::Sqlite(err)
+    }
+}
+
+/// A u128 stored as decimal text, since SQLite integers are 64-bit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Amount(u128);
+
+impl ToSql for Amount {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.to_string()))
+    }
+}
+
+impl FromSql for Amount {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map(Amount).map_err(|err| FromSqlError::Other(Box::new(err)))
+    }
+}
+
+/// A status stored by its name.
+struct StatusName(Status);
+
+impl ToSql for StatusName {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(format!("{:?}", self.0)))
+    }
+}
+
+impl FromSql for StatusName {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let name = Value::String(value.as_str()?.to_string());
+        serde_json::from_value(name).map(StatusName).map_err(|err| FromSqlError::Other(Box::new(err)))
+    }
+}
+
+/// A payment as far as the contract's logs tell. Metadata, tip receivers and
+/// auto-confirm windows are not logged, so they are not part of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedPayment {
+    pub payment_id: u128,
+    pub order_id: u128,
+    pub shop: AccountId,
+    pub user: Option<AccountId>,
+    pub payer: Option<AccountId>,
+    pub parent_id: Option<u128>,
+    pub msg: String,
+    pub fee: Balance,
+    pub discount: Balance,
+    pub tip: Balance,
+    pub coupon: Option<String>,
+    pub status: Status,
+    pub uses: u64,
+    /// Block height of the last event about the payment.
+    pub updated_at: u64,
+}
+
+impl IndexedPayment {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(IndexedPayment {
+            payment_id: row.get::<_, Amount>(0)?.0,
+            order_id: row.get::<_, Amount>(1)?.0,
+            shop: row.get(2)?,
+            user: row.get(3)?,
+            payer: row.get(4)?,
+            parent_id: row.get::<_, Option<Amount>>(5)?.map(|parent_id| parent_id.0),
+            msg: row.get(6)?,
+            fee: row.get::<_, Amount>(7)?.0,
+            discount: row.get::<_, Amount>(8)?.0,
+            tip: row.get::<_, Amount>(9)?.0,
+            coupon: row.get(10)?,
+            status: row.get::<_, StatusName>(11)?.0,
+            uses: row.get::<_, i64>(12)? as u64,
+            updated_at: row.get::<_, i64>(13)? as u64,
+        })
+    }
+
+    fn save(&self, conn: &Connection) -> Result<(), IndexerError> {
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO payments ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)", PAYMENT_COLUMNS),
+            params![
+                Amount(self.payment_id),
+                Amount(self.order_id),
+                self.shop,
+                self.user,
+                self.payer,
+                self.parent_id.map(Amount),
+                self.msg,
+                Amount(self.fee),
+                Amount(self.discount),
+                Amount(self.tip),
+                self.coupon,
+                StatusName(self.status.clone()),
+                self.uses as i64,
+                self.updated_at as i64,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl From<&PaymentJson> for IndexedPayment {
+    fn from(payment: &PaymentJson) -> Self {
+        IndexedPayment {
+            payment_id: payment.payment_id.0,
+            order_id: payment.order_id.0,
+            shop: payment.shop.clone(),
+            user: payment.user.clone(),
+            payer: payment.payer.clone(),
+            parent_id: payment.parent_id.map(|parent_id| parent_id.0),
+            msg: payment.msg.clone(),
+            fee: payment.fee.0,
+            discount: payment.discount.0,
+            tip: payment.tip.0,
+            coupon: payment.coupon.clone(),
+            status: payment.status.clone(),
+            uses: payment.uses,
+            updated_at: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShopSummary {
+    pub shop: AccountId,
+    /// Payments requested by the shop, including children of its payment links.
+    pub payments: u64,
+    /// Paid out to the shop by claims, tips included unless they went to a tip receiver.
+    pub claimed: Balance,
+    pub refunded: Balance,
+    /// Platform fees taken from the shop's claims.
+    pub fees: Balance,
+}
+
+impl ShopSummary {
+    fn get(conn: &Connection, shop: &str) -> Result<Option<Self>, IndexerError> {
+        let summary = conn
+            .query_row("SELECT shop, payments, claimed, refunded, fees FROM shops WHERE shop = ?1", params![shop], |row| {
+                Ok(ShopSummary {
+                    shop: row.get(0)?,
+                    payments: row.get::<_, i64>(1)? as u64,
+                    claimed: row.get::<_, Amount>(2)?.0,
+                    refunded: row.get::<_, Amount>(3)?.0,
+                    fees: row.get::<_, Amount>(4)?.0,
+                })
+            })
+            .optional()?;
+        Ok(summary)
+    }
+
+    fn update<F: FnOnce(&mut ShopSummary)>(conn: &Connection, shop: &str, f: F) -> Result<(), IndexerError> {
+        let mut summary = ShopSummary::get(conn, shop)?.unwrap_or(ShopSummary { shop: shop.to_string(), ..Default::default() });
+        f(&mut summary);
+        conn.execute(
+            "INSERT OR REPLACE INTO shops (shop, payments, claimed, refunded, fees) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![summary.shop, summary.payments as i64, Amount(summary.claimed), Amount(summary.refunded), Amount(summary.fees)],
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlatformFees {
+    pub collected: Balance,
+    pub withdrawn: Balance,
+}
+
+/// A value the projection and the contract disagree on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    /// None for contract-wide totals.
+    pub payment_id: Option<u128>,
+    pub field: &'static str,
+    pub indexed: String,
+    pub contract: String,
+}
+
+fn compare<T: fmt::Debug + PartialEq>(mismatches: &mut Vec<Mismatch>, payment_id: Option<u128>, field: &'static str, indexed: T, contract: T) {
+    if indexed != contract {
+        mismatches.push(Mismatch { payment_id, field, indexed: format!("{:?}", indexed), contract: format!("{:?}", contract) });
+    }
+}
+
+fn get_payment(conn: &Connection, payment_id: u128) -> Result<Option<IndexedPayment>, IndexerError> {
+    let sql = format!("SELECT {} FROM payments WHERE payment_id = ?1", PAYMENT_COLUMNS);
+    Ok(conn.query_row(&sql, params![Amount(payment_id)], IndexedPayment::from_row).optional()?)
+}
+
+fn require_payment(conn: &Connection, payment_id: u128) -> Result<IndexedPayment, IndexerError> {
+    get_payment(conn, payment_id)?.ok_or(IndexerError::UnknownPayment(payment_id))
+}
+
+fn set_status(conn: &Connection, payment_id: u128, status: Status, block_height: u64) -> Result<IndexedPayment, IndexerError> {
+    let mut payment = require_payment(conn, payment_id)?;
+    payment.status = status;
+    payment.updated_at = block_height;
+    payment.save(conn)?;
+    Ok(payment)
+}
+
+/// Applies one event of `outcome` to the projection.
+fn apply(conn: &Connection, outcome: &ExecutionOutcome, event: Event) -> Result<(), IndexerError> {
+    let block_height = outcome.block_height;
+
+    match event {
+        Event::RequestPayment { payment_id, order_id, user, fee, msg } => {
+            // req_payment and req_payments are called by the shop itself
+            let shop = outcome.predecessor_id.clone();
+            IndexedPayment {
+                payment_id,
+                order_id,
+                shop: shop.clone(),
+                user,
+                payer: None,
+                parent_id: None,
+                msg,
+                fee,
+                discount: 0,
+                tip: 0,
+                coupon: None,
+                status: Status::REQUESTING,
+                uses: 0,
+                updated_at: block_height,
+            }
+            .save(conn)?;
+            ShopSummary::update(conn, &shop, |summary| summary.payments += 1)?;
+        }
+        Event::Pay { payment_id, parent_id, payer, coupon, discount, tip } => {
+            if let Some(parent_id) = parent_id {
+                // paying a payment link spawns a child payment for the caller
+                let mut link = require_payment(conn, parent_id)?;
+                link.uses += 1;
+                link.updated_at = block_height;
+                link.save(conn)?;
+
+                IndexedPayment {
+                    payment_id,
+                    user: Some(outcome.predecessor_id.clone()),
+                    parent_id: Some(parent_id),
+                    uses: 0,
+                    ..link.clone()
+                }
+                .save(conn)?;
+                ShopSummary::update(conn, &link.shop, |summary| summary.payments += 1)?;
+            }
+
+            let mut payment = require_payment(conn, payment_id)?;
+            if payment.user.is_none() {
+                payment.user = Some(outcome.predecessor_id.clone());
+            }
+            payment.payer = payer;
+            payment.coupon = coupon;
+            payment.discount = discount;
+            payment.tip = tip;
+            payment.status = Status::PAID;
+            payment.updated_at = block_height;
+            payment.save(conn)?;
+            report::record(conn, outcome, &payment.shop, Some(payment_id), Entry::Paid, payment.fee - payment.discount + payment.tip)?;
+        }
+        Event::Confirm { payment_id } | Event::AutoConfirm { payment_id } => {
+            set_status(conn, payment_id, Status::CONFIRMED, block_height)?;
+        }
+        Event::Ship { payment_id } => {
+            set_status(conn, payment_id, Status::SHIPPED, block_height)?;
+        }
+        Event::Deliver { payment_id } => {
+            set_status(conn, payment_id, Status::DELIVERED, block_height)?;
+        }
+        Event::Dispute { payment_id } => {
+            set_status(conn, payment_id, Status::DISPUTED, block_height)?;
+        }
+        Event::ShopClaim { payment_id, amount, storage, tip, .. } => {
+            let payment = set_status(conn, payment_id, Status::CLAIMED, block_height)?;
+            // the history storage kept out of the shop's amount is still the shop's
+            let amount = amount + storage;
+            // what the payment held, less what went to the shop and the tip receiver
+            let fee = (payment.fee - payment.discount + payment.tip)
+                .checked_sub(amount + tip)
+                .ok_or(IndexerError::InvalidClaim(payment_id))?;
+
+            conn.execute(
+                "INSERT OR REPLACE INTO fees (payment_id, shop, amount, block_height) VALUES (?1, ?2, ?3, ?4)",
+                params![Amount(payment_id), payment.shop, Amount(fee), block_height as i64],
+            )?;
+            report::record(conn, outcome, &payment.shop, Some(payment_id), Entry::Claimed, amount)?;
+            report::record(conn, outcome, &payment.shop, Some(payment_id), Entry::Tip, tip)?;
+            report::record(conn, outcome, &payment.shop, Some(payment_id), Entry::Fee, fee)?;
+            ShopSummary::update(conn, &payment.shop, |summary| {
+                summary.claimed += amount;
+                summary.fees += fee;
+            })?;
+        }
+        Event::Refund { payment_id, amount, storage, .. } => {
+            let payment = set_status(conn, payment_id, Status::REFUNDED, block_height)?;
+            let amount = amount + storage;
+            ShopSummary::update(conn, &payment.shop, |summary| summary.refunded += amount)?;
+            report::record(conn, outcome, &payment.shop, Some(payment_id), Entry::Refunded, amount)?;
+        }
+        Event::ArchivePayment(archived) => {
+            // the log carries the last state, so payments indexed late are still recorded
+            let mut payment = get_payment(conn, archived.payment_id.0)?.unwrap_or_else(|| IndexedPayment::from(&*archived));
+            payment.status = Status::ARCHIVED;
+            payment.updated_at = block_height;
+            payment.save(conn)?;
+        }
+        Event::Withdraw { amount, .. } => {
+            conn.execute(
+                "INSERT INTO withdrawals (receipt_id, amount, block_height) VALUES (?1, ?2, ?3)",
+                params![outcome.receipt_id, Amount(amount), block_height as i64],
+            )?;
+            report::record(conn, outcome, "", None, Entry::Withdrawn, amount)?;
+        }
+        // claim_many also logs every claim, pay_many every payment
+        Event::ShopClaimMany { .. } | Event::PayMany { .. } => {}
+        // a withdrawal is only logged as `Withdraw` once its transfer went through
+        Event::WithdrawFailed { .. } => {}
+        Event::SetPaymentFee { .. }
+        | Event::SetTipFee { .. }
+        | Event::CreateCoupon { .. }
+        | Event::StorageDeposit { .. }
+        | Event::StorageWithdraw { .. }
+        | Event::SetApprovers { .. }
+        | Event::ProposeWithdrawal { .. }
+        | Event::ProposeApprovers { .. }
+        | Event::ApproveProposal { .. }
+        | Event::CancelProposal { .. }
+        | Event::Migrate { .. } => {}
+    }
+
+    Ok(())
+}
+
+pub struct Indexer {
+    conn: Connection,
+    contract_id: AccountId,
+}
+
+impl Indexer {
+    pub fn open<P: AsRef<Path>>(path: P, contract_id: AccountId) -> Result<Self, IndexerError> {
+        Indexer::new(Connection::open(path)?, contract_id)
+    }
+
+    /// Indexes into `conn`, creating the tables it is missing.
+    pub fn new(conn: Connection, contract_id: AccountId) -> Result<Self, IndexerError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Indexer { conn, contract_id })
+    }
+
+    /// The projection, for queries of its own.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Applies the events of one outcome in a single transaction. Returns false for
+    /// outcomes of other contracts, failed ones and ones already indexed.
+    pub fn process(&mut self, outcome: &ExecutionOutcome) -> Result<bool, IndexerError> {
+        if outcome.executor_id != self.contract_id || !outcome.success {
+            return Ok(false);
+        }
+
+        let tx = self.conn.transaction()?;
+        let seen = tx
+            .query_row("SELECT 1 FROM receipts WHERE receipt_id = ?1", params![outcome.receipt_id], |_| Ok(()))
+            .optional()?;
+        if seen.is_some() {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO receipts (receipt_id, block_height) VALUES (?1, ?2)",
+            params![outcome.receipt_id, outcome.block_height as i64],
+        )?;
+
+        for log in outcome.logs.iter() {
+            match Event::parse(log) {
+                Ok(event) => apply(&tx, outcome, event)?,
+                Err(EventParseError::UnknownEvent(_)) => {}
+                Err(error) => return Err(IndexerError::Event { receipt_id: outcome.receipt_id.clone(), error }),
+            }
+        }
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Processes outcomes in order and returns how many were applied.
+    pub fn process_all<I>(&mut self, outcomes: I) -> Result<usize, IndexerError>
+    where
+        I: IntoIterator<Item = Result<ExecutionOutcome, IndexerError>>,
+    {
+        let mut processed = 0;
+        for outcome in outcomes {
+            if self.process(&outcome?)? {
+                processed += 1;
+            }
+        }
+        Ok(processed)
+    }
+
+    pub fn payment(&self, payment_id: u128) -> Result<Option<IndexedPayment>, IndexerError> {
+        get_payment(&self.conn, payment_id)
+    }
+
+    /// Every indexed payment, by pay_id.
+    pub fn payments(&self) -> Result<Vec<IndexedPayment>, IndexerError> {
+        let mut statement = self.conn.prepare(&format!("SELECT {} FROM payments", PAYMENT_COLUMNS))?;
+        let mut payments = statement
+            .query_map(params![], IndexedPayment::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        payments.sort_by_key(|payment| payment.payment_id);
+        Ok(payments)
+    }
+
+    pub fn shop(&self, shop: &str) -> Result<Option<ShopSummary>, IndexerError> {
+        ShopSummary::get(&self.conn, shop)
+    }
+
+    pub fn platform_fees(&self) -> Result<PlatformFees, IndexerError> {
+        let sum = |sql: &str| -> Result<Balance, IndexerError> {
+            let mut statement = self.conn.prepare(sql)?;
+            let amounts = statement.query_map(params![], |row| row.get::<_, Amount>(0))?;
+            let mut total = 0;
+            for amount in amounts {
+                total += amount?.0;
+            }
+            Ok(total)
+        };
+
+        Ok(PlatformFees {
+            collected: sum("SELECT amount FROM fees")?,
+            withdrawn: sum("SELECT amount FROM withdrawals")?,
+        })
+    }
+
+    /// Compares every indexed payment with what `get_payment_info` returns for it.
+    pub fn reconcile<F>(&self, mut get_payment_info: F) -> Result<Vec<Mismatch>, IndexerError>
+    where
+        F: FnMut(u128) -> Option<PaymentJson>,
+    {
+        let mut mismatches = vec![];
+        for payment in self.payments()? {
+            let id = Some(payment.payment_id);
+            let contract = match get_payment_info(payment.payment_id) {
+                Some(contract) => contract,
+                None => {
+                    compare(&mut mismatches, id, "payment", "indexed", "missing");
+                    continue;
+                }
+            };
+
+            compare(&mut mismatches, id, "status", &payment.status, &contract.status);
+            if contract.status == Status::ARCHIVED {
+                // nothing but the status is left of archived payments
+                continue;
+            }
+            compare(&mut mismatches, id, "order_id", payment.order_id, contract.order_id.0);
+            compare(&mut mismatches, id, "shop", &payment.shop, &contract.shop);
+            compare(&mut mismatches, id, "user", &payment.user, &contract.user);
+            compare(&mut mismatches, id, "payer", &payment.payer, &contract.payer);
+            compare(&mut mismatches, id, "parent_id", payment.parent_id, contract.parent_id.map(|parent_id| parent_id.0));
+            compare(&mut mismatches, id, "fee", payment.fee, contract.fee.0);
+            compare(&mut mismatches, id, "discount", payment.discount, contract.discount.0);
+            compare(&mut mismatches, id, "tip", payment.tip, contract.tip.0);
+            compare(&mut mismatches, id, "coupon", &payment.coupon, &contract.coupon);
+            compare(&mut mismatches, id, "uses", payment.uses, contract.uses);
+        }
+        Ok(mismatches)
+    }
+
+    /// Compares the indexed totals with `get_payment_shop_info`, which only holds
+    /// when the contract was indexed from its first block.
+    pub fn reconcile_totals(&self, shop_info: &PaymentShopJson) -> Result<Vec<Mismatch>, IndexerError> {
+        let mut mismatches = vec![];
+        let payments = self.payments()?;
+        compare(&mut mismatches, None, "pay_id", payments.last().map_or(0, |payment| payment.payment_id), shop_info.pay_id.0);
+        compare(&mut mismatches, None, "total_payment", self.platform_fees()?.collected, shop_info.total_payment.0);
+        Ok(mismatches)
+    }
+
+    /// Funds moved per day and shop, by day and then shop. See [`write_csv`].
+    pub fn daily_report(&self) -> Result<Vec<DailyShopReport>, IndexerError> {
+        report::daily_reports(&self.conn)
+    }
+
+    /// Compares the report's totals with `get_balance_breakdown`: the escrow changes
+    /// must add up to the escrow, and the fees less withdrawals to the pending fees.
+    /// Like [`Indexer::reconcile_totals`], this needs the contract indexed from its first block.
+    pub fn reconcile_balance(&self, breakdown: &BalanceBreakdownJson) -> Result<Vec<Mismatch>, IndexerError> {
+        let reports = self.daily_report()?;
+        let escrow: i128 = reports.iter().map(DailyShopReport::escrow_change).sum();
+        let fees: Balance = reports.iter().map(|report| report.fees).sum();
+        let withdrawn: Balance = reports.iter().map(|report| report.withdrawn).sum();
+
+        let mut mismatches = vec![];
+        compare(&mut mismatches, None, "escrow", escrow, breakdown.escrow.0 as i128);
+        compare(&mut mismatches, None, "pending_fees", fees as i128 - withdrawn as i128, breakdown.pending_fees.0 as i128);
+        compare(&mut mismatches, None, "shortfall", 0, breakdown.shortfall.0);
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::json_types::U128;
+
+    const CONTRACT: &str = "payment_shop_contract";
+
+    fn indexer() -> Indexer {
+        Indexer::new(Connection::open_in_memory().unwrap(), CONTRACT.to_string()).unwrap()
+    }
+
+    fn outcome(receipt_id: &str, block_height: u64, predecessor_id: &str, logs: &[&str]) -> ExecutionOutcome {
+        ExecutionOutcome {
+            receipt_id: receipt_id.to_string(),
+            block_height,
+            // one block a day from 2021-01-01
+            block_timestamp: (1_609_459_200 + block_height * 86_400) * 1_000_000_000,
+            predecessor_id: predecessor_id.to_string(),
+            executor_id: CONTRACT.to_string(),
+            logs: logs.iter().map(|log| log.to_string()).collect(),
+            success: true,
+        }
+    }
+
+    /// Payment 1 of 10 from alice to bob, paid and confirmed.
+    fn confirmed() -> Indexer {
+        let mut indexer = indexer();
+        indexer.process(&outcome("r1", 1, "alice", &["Request payment: payment_id: 1, order_id: 7, account: bob, fee: 10, data: Hello"])).unwrap();
+        indexer.process(&outcome("r2", 2, "bob", &["Pay: payment_id: 1, tip 2"])).unwrap();
+        indexer.process(&outcome("r3", 3, "bob", &["Confirm: payment_id: 1"])).unwrap();
+        indexer
+    }
+
+    fn payment_json(payment: &IndexedPayment) -> PaymentJson {
+        serde_json::from_value(serde_json::json!({
+            "payment_id": payment.payment_id.to_string(),
+            "order_id": payment.order_id.to_string(),
+            "shop": payment.shop,
+            "user": payment.user,
+            "msg": payment.msg,
+            "fee": payment.fee.to_string(),
+            "status": payment.status,
+            "max_uses": null,
+            "uses": payment.uses,
+            "parent_id": payment.parent_id.map(|parent_id| parent_id.to_string()),
+            "payer": payment.payer,
+            "paid_at": "0",
+            "metadata": null,
+            "discount": payment.discount.to_string(),
+            "coupon": payment.coupon,
+            "tip": payment.tip.to_string(),
+            "tip_receiver": null,
+            "auto_confirm_after": null,
+            "tracking_ref": null,
+            "shipped_at": "0",
+            "delivered_at": "0"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_payment_flow() {
+        let mut indexer = confirmed();
+        indexer.process(&outcome("r4", 4, "alice", &["Shop claim: payment_id: 1, amount 10"])).unwrap();
+        indexer.process(&outcome("r5", 5, "owner", &["Withdraw: amount 2"])).unwrap();
+
+        let payment = indexer.payment(1).unwrap().unwrap();
+        assert_eq!((payment.shop.as_str(), payment.user.as_deref()), ("alice", Some("bob")));
+        assert_eq!((payment.status, payment.tip, payment.updated_at), (Status::CLAIMED, 2, 4));
+
+        let shop = indexer.shop("alice").unwrap().unwrap();
+        assert_eq!((shop.payments, shop.claimed, shop.fees), (1, 10, 2));
+        assert_eq!(indexer.platform_fees().unwrap(), PlatformFees { collected: 2, withdrawn: 2 });
+    }
+
+    #[test]
+    fn test_claim_paying_for_history() {
+        let mut indexer = confirmed();
+        indexer.process(&outcome("r4", 4, "alice", &["Shop claim: payment_id: 1, amount 9, storage 1"])).unwrap();
+
+        let shop = indexer.shop("alice").unwrap().unwrap();
+        assert_eq!((shop.claimed, shop.fees), (10, 2));
+        assert_eq!(indexer.daily_report().unwrap()[1].escrow_change(), -12);
+    }
+
+    #[test]
+    fn test_payment_link() {
+        let mut indexer = indexer();
+        indexer.process(&outcome("r1", 1, "alice", &["Request payment: payment_id: 1, order_id: 7, account: , fee: 10, data: "])).unwrap();
+        indexer.process(&outcome("r2", 2, "carol", &["Pay: payment_id: 2, parent_id: 1"])).unwrap();
+
+        let link = indexer.payment(1).unwrap().unwrap();
+        assert_eq!((link.status, link.uses, link.user), (Status::REQUESTING, 1, None));
+
+        let child = indexer.payment(2).unwrap().unwrap();
+        assert_eq!((child.status, child.parent_id, child.user.as_deref()), (Status::PAID, Some(1), Some("carol")));
+        assert_eq!((child.order_id, child.fee), (7, 10));
+        assert_eq!(indexer.shop("alice").unwrap().unwrap().payments, 2);
+    }
+
+    #[test]
+    fn test_skipped_outcomes() {
+        let mut indexer = confirmed();
+        let dispute = outcome("r4", 4, "bob", &["Dispute: payment_id: 1"]);
+
+        assert!(!indexer.process(&outcome("r3", 3, "bob", &["Confirm: payment_id: 1"])).unwrap());
+        assert!(!indexer.process(&ExecutionOutcome { success: false, ..dispute.clone() }).unwrap());
+        assert!(!indexer.process(&ExecutionOutcome { executor_id: "other".to_string(), ..dispute }).unwrap());
+        assert_eq!(indexer.payment(1).unwrap().unwrap().status, Status::CONFIRMED);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut indexer = indexer();
+        assert!(matches!(indexer.process(&outcome("r1", 1, "bob", &["Confirm: payment_id: 9"])), Err(IndexerError::UnknownPayment(9))));
+        assert!(matches!(indexer.process(&outcome("r2", 1, "bob", &["Confirm: pay_id: 9"])), Err(IndexerError::Event { .. })));
+        // a failed outcome leaves nothing behind, so it can be retried
+        assert_eq!(indexer.connection().query_row("SELECT COUNT(*) FROM receipts", params![], |row| row.get::<_, i64>(0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_json_lines_source() {
+        let lines = format!(
+            "{}\n\n{}\n",
+            serde_json::to_string(&outcome("r1", 1, "alice", &["Request payment: payment_id: 1, order_id: 7, account: bob, fee: 10, data: Hello"])).unwrap(),
+            serde_json::to_string(&outcome("r2", 2, "bob", &["Pay: payment_id: 1"])).unwrap()
+        );
+
+        let mut indexer = indexer();
+        assert_eq!(indexer.process_all(JsonLinesSource::new(lines.as_bytes())).unwrap(), 2);
+        assert_eq!(indexer.payment(1).unwrap().unwrap().status, Status::PAID);
+        assert!(JsonLinesSource::new(&b"{\"receipt_id\": 1}"[..]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let indexer = confirmed();
+        let payment = indexer.payment(1).unwrap().unwrap();
+        assert_eq!(indexer.reconcile(|_| Some(payment_json(&payment))).unwrap(), vec![]);
+
+        let claimed = IndexedPayment { status: Status::CLAIMED, ..payment.clone() };
+        assert_eq!(indexer.reconcile(|_| Some(payment_json(&claimed))).unwrap(), vec![Mismatch {
+            payment_id: Some(1),
+            field: "status",
+            indexed: "CONFIRMED".to_string(),
+            contract: "CLAIMED".to_string(),
+        }]);
+        assert_eq!(indexer.reconcile(|_| None).unwrap()[0].field, "payment");
+
+        let shop_info = PaymentShopJson { pay_id: U128(1), payment_fee_percent: U128(20000), total_payment: U128(0), tip_fee_percent: U128(0) };
+        assert_eq!(indexer.reconcile_totals(&shop_info).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_daily_report() {
+        let mut indexer = confirmed();
+        indexer.process(&outcome("r4", 4, "alice", &["Shop claim: payment_id: 1, amount 10"])).unwrap();
+        indexer.process(&outcome("r5", 5, "owner", &["Withdraw: amount 2"])).unwrap();
+
+        let reports = indexer.daily_report().unwrap();
+        assert_eq!(reports[1], DailyShopReport {
+            day: "2021-01-05".to_string(),
+            shop: "alice".to_string(),
+            claimed: 10,
+            fees: 2,
+            ..Default::default()
+        });
+        assert_eq!(reports[1].escrow_change(), -12);
+
+        let mut csv = vec![];
+        write_csv(&reports, &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().collect::<Vec<_>>(), vec![
+            CSV_HEADER,
+            "2021-01-03,alice,12,0,0,0,0,0,12",
+            "2021-01-05,alice,0,10,0,2,0,0,-12",
+            "2021-01-06,,0,0,0,0,0,2,0",
+        ]);
+    }
+
+    #[test]
+    fn test_daily_report_unknown_kind() {
+        let indexer = confirmed();
+        indexer.connection().execute(
+            "INSERT INTO ledger (receipt_id, block_timestamp, shop, payment_id, kind, amount) VALUES ('r9', 0, 'alice', NULL, 'bonus', '1')",
+            params![],
+        ).unwrap();
+
+        assert!(matches!(indexer.daily_report(), Err(IndexerError::UnknownLedgerKind(kind)) if kind == "bonus"));
+    }
+
+    #[test]
+    fn test_reconcile_balance() {
+        let indexer = confirmed();
+        let mut breakdown = BalanceBreakdownJson {
+            balance: U128(100),
+            escrow_by_status: vec![],
+            escrow: U128(12),
+            pending_fees: U128(0),
+            storage_locked: U128(80),
+            storage_available: U128(0),
+            surplus: U128(8),
+            shortfall: U128(0),
+        };
+        assert_eq!(indexer.reconcile_balance(&breakdown).unwrap(), vec![]);
+
+        breakdown.escrow = U128(10);
+        breakdown.shortfall = U128(2);
+        let fields: Vec<_> = indexer.reconcile_balance(&breakdown).unwrap().iter().map(|mismatch| mismatch.field).collect();
+        assert_eq!(fields, vec!["escrow", "shortfall"]);
+    }
+}
@@ -0,0 +1,37 @@
+use clap::Parser;
+use payment_shop_indexer::{write_csv, Indexer, IndexerError, JsonLinesSource};
+use std::io;
+use std::path::PathBuf;
+
+/// Index outcomes into a SQLite projection and print the daily per-shop report as CSV.
+#[derive(Parser, Debug)]
+#[clap(name = "payment-shop-indexer")]
+struct Cli {
+    /// Account of the payment shop contract.
+    #[clap(long)]
+    contract: String,
+
+    /// SQLite database, created if missing.
+    #[clap(long)]
+    db: PathBuf,
+
+    /// Outcomes to index first, one JSON object per line.
+    #[clap(long)]
+    outcomes: Option<PathBuf>,
+}
+
+fn run(cli: &Cli) -> Result<(), IndexerError> {
+    let mut indexer = Indexer::open(&cli.db, cli.contract.clone())?;
+    if let Some(outcomes) = &cli.outcomes {
+        let processed = indexer.process_all(JsonLinesSource::open(outcomes)?)?;
+        eprintln!("indexed {} outcomes", processed);
+    }
+    write_csv(&indexer.daily_report()?, io::stdout().lock())
+}
+
+fn main() {
+    if let Err(err) = run(&Cli::parse()) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,136 @@
+use near_sdk::{AccountId, Balance};
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::{Amount, ExecutionOutcome, IndexerError};
+
+/// What a ledger entry moved, stored by name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Entry {
+    /// Into escrow: the fee less the discount, plus the tip.
+    Paid,
+    /// Out of escrow to the shop.
+    Claimed,
+    /// Out of escrow to a tip receiver.
+    Tip,
+    /// Out of escrow into the platform fees.
+    Fee,
+    /// Out of escrow back to the payer.
+    Refunded,
+    /// Out of the platform fees to the owner.
+    Withdrawn,
+}
+
+impl Entry {
+    fn name(&self) -> &'static str {
+        match self {
+            Entry::Paid => "paid",
+            Entry::Claimed => "claimed",
+            Entry::Tip => "tip",
+            Entry::Fee => "fee",
+            Entry::Refunded => "refunded",
+            Entry::Withdrawn => "withdrawn",
+        }
+    }
+}
+
+/// Records a movement of funds made by `outcome`. `shop` is empty for withdrawals.
+pub(crate) fn record(
+    conn: &Connection,
+    outcome: &ExecutionOutcome,
+    shop: &str,
+    payment_id: Option<u128>,
+    entry: Entry,
+    amount: Balance,
+) -> Result<(), IndexerError> {
+    if amount == 0 {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO ledger (receipt_id, block_timestamp, shop, payment_id, kind, amount) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![outcome.receipt_id, outcome.block_timestamp as i64, shop, payment_id.map(Amount), entry.name(), Amount(amount)],
+    )?;
+    Ok(())
+}
+
+/// The funds a shop moved in one UTC day.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DailyShopReport {
+    /// As YYYY-MM-DD.
+    pub day: String,
+    /// Empty for the platform's withdrawals.
+    pub shop: AccountId,
+    /// Paid into escrow: fees less discounts, plus tips.
+    pub paid: Balance,
+    pub claimed: Balance,
+    /// Paid out to tip receivers.
+    pub tips: Balance,
+    /// Platform fees taken by claims.
+    pub fees: Balance,
+    pub refunded: Balance,
+    /// Platform fees withdrawn by the owner.
+    pub withdrawn: Balance,
+}
+
+impl DailyShopReport {
+    /// How much the shop's escrow grew that day, negative when more left it than came in.
+    pub fn escrow_change(&self) -> i128 {
+        self.paid as i128 - (self.claimed + self.tips + self.fees + self.refunded) as i128
+    }
+
+    fn add(&mut self, entry: Entry, amount: Balance) {
+        let total = match entry {
+            Entry::Paid => &mut self.paid,
+            Entry::Claimed => &mut self.claimed,
+            Entry::Tip => &mut self.tips,
+            Entry::Fee => &mut self.fees,
+            Entry::Refunded => &mut self.refunded,
+            Entry::Withdrawn => &mut self.withdrawn,
+        };
+        *total += amount;
+    }
+}
+
+/// Sums the ledger by day and shop, in that order.
+pub(crate) fn daily_reports(conn: &Connection) -> Result<Vec<DailyShopReport>, IndexerError> {
+    let mut statement = conn.prepare("SELECT date(block_timestamp / 1000000000, 'unixepoch'), shop, kind, amount FROM ledger")?;
+    let rows = statement.query_map(params![], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, Amount>(3)?))
+    })?;
+
+    let entries = [Entry::Paid, Entry::Claimed, Entry::Tip, Entry::Fee, Entry::Refunded, Entry::Withdrawn];
+    let mut reports: BTreeMap<(String, String), DailyShopReport> = BTreeMap::new();
+    for row in rows {
+        let (day, shop, kind, amount) = row?;
+        let entry = *entries.iter().find(|entry| entry.name() == kind).ok_or(IndexerError::UnknownLedgerKind(kind))?;
+        reports
+            .entry((day.clone(), shop.clone()))
+            .or_insert_with(|| DailyShopReport { day, shop, ..Default::default() })
+            .add(entry, amount.0);
+    }
+    Ok(reports.into_values().collect())
+}
+
+pub const CSV_HEADER: &str = "day,shop,paid,claimed,tips,fees,refunded,withdrawn,escrow_change";
+
+/// Writes `reports` as CSV with a header line. Account ids and dates never need quoting.
+pub fn write_csv<W: Write>(reports: &[DailyShopReport], mut writer: W) -> Result<(), IndexerError> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+    for report in reports {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            report.day,
+            report.shop,
+            report.paid,
+            report.claimed,
+            report.tips,
+            report.fees,
+            report.refunded,
+            report.withdrawn,
+            report.escrow_change()
+        )?;
+    }
+    Ok(())
+}
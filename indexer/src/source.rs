@@ -0,0 +1,58 @@
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::AccountId;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::Path;
+
+use crate::IndexerError;
+
+/// The part of a receipt's execution outcome the indexer needs.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExecutionOutcome {
+    pub receipt_id: String,
+    pub block_height: u64,
+    /// Nanoseconds since the epoch. Older exports without it land on 1970-01-01 in reports.
+    #[serde(default)]
+    pub block_timestamp: u64,
+    pub predecessor_id: AccountId,
+    pub executor_id: AccountId,
+    pub logs: Vec<String>,
+    /// Failed receipts are skipped: their state changes were reverted.
+    pub success: bool,
+}
+
+/// Reads outcomes written one JSON object per line, as exported from a node or an archive.
+pub struct JsonLinesSource<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> JsonLinesSource<R> {
+    pub fn new(reader: R) -> Self {
+        JsonLinesSource { lines: reader.lines() }
+    }
+}
+
+impl JsonLinesSource<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IndexerError> {
+        Ok(JsonLinesSource::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesSource<R> {
+    type Item = Result<ExecutionOutcome, IndexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            if !line.trim().is_empty() {
+                return Some(serde_json::from_str(&line).map_err(IndexerError::from));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,94 @@
+use near_sdk::serde_json::json;
+use near_sdk_sim::{init_simulator, to_yocto, ExecutionResult, UserAccount, STORAGE_AMOUNT, DEFAULT_GAS};
+use payment_shop_client::{FunctionCall, PaymentShopClient, Status};
+use payment_shop_indexer::{ExecutionOutcome, Indexer};
+use rusqlite::Connection;
+
+#[path = "../../tests/common/contract.rs"]
+mod contract;
+
+const PAYMENT_SHOP_CONTRACT_ID: &str = "payment_shop_contract";
+
+/// Stands in for a node: runs the call and turns its receipts into outcomes.
+struct Chain {
+    block_height: u64,
+    outcomes: Vec<ExecutionOutcome>,
+}
+
+impl Chain {
+    fn call(&mut self, account: &UserAccount, call: FunctionCall) {
+        let result = account.call(call.receiver_id, &call.method_name, &call.args, call.gas, call.deposit);
+        result.assert_success();
+
+        self.block_height += 1;
+        for receipt in result.promise_results().into_iter().flatten() {
+            self.push(account, &receipt);
+        }
+    }
+
+    fn push(&mut self, account: &UserAccount, receipt: &ExecutionResult) {
+        self.outcomes.push(ExecutionOutcome {
+            receipt_id: format!("receipt-{}", self.outcomes.len()),
+            block_height: self.block_height,
+            block_timestamp: account.borrow_runtime().current_block().block_timestamp,
+            predecessor_id: account.account_id(),
+            executor_id: receipt.outcome().executor_id.clone(),
+            logs: receipt.logs().clone(),
+            success: receipt.is_ok(),
+        });
+    }
+}
+
+#[test]
+pub fn test_indexer_reconciles_with_contract() {
+    let root = init_simulator(None);
+    let alice = root.create_user("alice".to_string(), to_yocto("100"));
+    let bod = root.create_user("bod".to_string(), to_yocto("100"));
+
+    root.deploy_and_init(
+        contract::wasm(),
+        PAYMENT_SHOP_CONTRACT_ID.to_string(),
+        "new",
+        &json!({
+            "owner_id": root.account_id(),
+            "payment_fee_percent": "20000"
+        }).to_string().as_bytes(),
+        STORAGE_AMOUNT,
+        DEFAULT_GAS
+    );
+
+    let client = PaymentShopClient::new(PAYMENT_SHOP_CONTRACT_ID.to_string());
+    let mut chain = Chain { block_height: 0, outcomes: vec![] };
+    chain.call(&alice, client.req_payment(1, to_yocto("10")).user("bod").msg("Hello").build());
+    chain.call(&alice, client.req_payment(2, to_yocto("5")).user("bod").build());
+    chain.call(&bod, client.pay(1, to_yocto("10")).build());
+    chain.call(&bod, client.pay(2, to_yocto("5")).build());
+    chain.call(&bod, client.confirm(1));
+    chain.call(&alice, client.claim(1));
+    chain.call(&alice, client.refund(2));
+    chain.call(&root, client.withdraw());
+
+    let mut indexer = Indexer::new(Connection::open_in_memory().unwrap(), PAYMENT_SHOP_CONTRACT_ID.to_string()).unwrap();
+    indexer.process_all(chain.outcomes.into_iter().map(Ok)).unwrap();
+
+    assert_eq!(indexer.payment(1).unwrap().unwrap().status, Status::CLAIMED);
+    assert_eq!(indexer.payment(2).unwrap().unwrap().status, Status::REFUNDED);
+    let fees = indexer.platform_fees().unwrap();
+    assert_eq!((fees.collected, fees.withdrawn), (to_yocto("2"), to_yocto("2")));
+
+    let mismatches = indexer.reconcile(|pay_id| {
+        let view = client.get_payment_info(pay_id);
+        view.parse(&root.view(view.receiver_id.clone(), &view.method_name, &view.args).unwrap()).unwrap()
+    }).unwrap();
+    assert_eq!(mismatches, vec![]);
+
+    let view = client.get_payment_shop_info();
+    let shop_info = view.parse(&root.view(view.receiver_id.clone(), &view.method_name, &view.args).unwrap()).unwrap();
+    assert_eq!(indexer.reconcile_totals(&shop_info).unwrap(), vec![]);
+
+    let view = client.get_balance_breakdown();
+    let breakdown = view.parse(&root.view(view.receiver_id.clone(), &view.method_name, &view.args).unwrap()).unwrap();
+    assert_eq!(indexer.reconcile_balance(&breakdown).unwrap(), vec![]);
+    let report = indexer.daily_report().unwrap();
+    assert_eq!(report.iter().map(|row| row.paid).sum::<u128>(), to_yocto("15"));
+}
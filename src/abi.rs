@@ -0,0 +1,273 @@
+//! Machine-readable description of the contract interface: every public method with
+//! its arguments, result and required deposit, the JSON types they use and the log
+//! lines the contract writes. It is checked in as `abi/payment_shop.json` so frontends
+//! can generate their types from it, and `test_abi_up_to_date` fails when they drift.
+//! `tests/abi.rs` checks the file against the exported methods without this feature.
+
+use crate::*;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde_json::{self, Value};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+
+/// Schema of `U128` and `U64`, which serialize as decimal strings.
+pub struct Decimal;
+
+impl JsonSchema for Decimal {
+    fn schema_name() -> String {
+        "Decimal".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^[0-9]+$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Maps an argument or result type to the type whose schema describes its JSON.
+pub trait AbiType {
+    type Schema: JsonSchema;
+    /// Whether the argument must be given. Only `Option`s may be left out.
+    const REQUIRED: bool = true;
+}
+
+macro_rules! abi_type_as_is {
+    ($($ty:ty),*) => {
+        $(impl AbiType for $ty {
+            type Schema = Self;
+        })*
+    };
+}
+
+abi_type_as_is!(
+    String, bool, u64, PaymentJson, PaymentRequest, PaymentMetadata, PaymentShopJson, PaymentStatsJson,
+    StatusChangeJson, CouponJson, Discount, StorageBalance, StorageBalanceBounds, BalanceBreakdownJson,
+    ProposalJson, ApproversJson
+);
+
+impl AbiType for U128 {
+    type Schema = Decimal;
+}
+
+impl AbiType for U64 {
+    type Schema = Decimal;
+}
+
+impl AbiType for ValidAccountId {
+    type Schema = String;
+}
+
+impl<T: AbiType> AbiType for Option<T> {
+    type Schema = Option<T::Schema>;
+    const REQUIRED: bool = false;
+}
+
+impl<T: AbiType> AbiType for Vec<T> {
+    type Schema = Vec<T::Schema>;
+}
+
+/// What a method expects attached.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Deposit {
+    None,
+    /// Exactly one yoctoNEAR, so the call needs a full access key.
+    OneYocto,
+    /// At least one yoctoNEAR. New storage is charged to the caller's storage balance.
+    Storage,
+    /// The amount due plus the storage the payment takes.
+    Payment,
+    /// Any amount, credited to the storage balance of `account_id` or the caller.
+    StorageBalance,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum MethodKind {
+    Init,
+    Call,
+    View,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AbiArg {
+    pub name: &'static str,
+    pub required: bool,
+    pub schema: Schema,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AbiMethod {
+    pub name: &'static str,
+    pub kind: MethodKind,
+    pub deposit: Deposit,
+    pub args: Vec<AbiArg>,
+    /// None for methods that return nothing.
+    pub returns: Option<Schema>,
+}
+
+/// A log line. Fields in brackets are only written when they apply, and lists of
+/// accounts are separated by spaces.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AbiEvent {
+    pub name: &'static str,
+    pub format: &'static str,
+}
+
+pub const EVENTS: &[AbiEvent] = &[
+    AbiEvent { name: "Request payment", format: "Request payment: payment_id: {payment_id}, order_id: {order_id}, account: {user}, fee: {fee}, data: {msg}" },
+    AbiEvent { name: "Pay", format: "Pay: payment_id: {payment_id}[, parent_id: {parent_id}][, payer: {payer}][, coupon: {coupon}, discount {discount}][, tip {tip}]" },
+    AbiEvent { name: "Pay many", format: "Pay many: count: {count}, amount {amount}, refund {refund}" },
+    AbiEvent { name: "Confirm", format: "Confirm: payment_id: {payment_id}" },
+    AbiEvent { name: "Auto confirm", format: "Auto confirm: payment_id: {payment_id}" },
+    AbiEvent { name: "Ship", format: "Ship: payment_id: {payment_id}" },
+    AbiEvent { name: "Deliver", format: "Deliver: payment_id: {payment_id}" },
+    AbiEvent { name: "Dispute", format: "Dispute: payment_id: {payment_id}" },
+    AbiEvent { name: "Shop claim", format: "Shop claim: payment_id: {payment_id}, amount {amount}[, storage {storage}][, tip_receiver: {tip_receiver}, tip {tip}]" },
+    AbiEvent { name: "Shop claim many", format: "Shop claim many: count: {count}, amount {amount}" },
+    AbiEvent { name: "Refund", format: "Refund: payment_id: {payment_id}, account: {account}, amount {amount}[, storage {storage}]" },
+    AbiEvent { name: "Archive payment", format: "Archive payment: {PaymentJson}" },
+    AbiEvent { name: "Withdraw", format: "Withdraw: amount {amount}[, receiver: {receiver}, proposal_id: {proposal_id}]" },
+    AbiEvent { name: "Withdraw failed", format: "Withdraw failed: amount {amount}, receiver: {receiver}" },
+    AbiEvent { name: "Set payment fee", format: "Set payment fee: {payment_fee_percent}" },
+    AbiEvent { name: "Set tip fee", format: "Set tip fee: {tip_fee_percent}" },
+    AbiEvent { name: "Create coupon", format: "Create coupon: shop: {shop}, code: {code}, max_uses: {max_uses}" },
+    AbiEvent { name: "Storage deposit", format: "Storage deposit: account: {account}, amount {amount}" },
+    AbiEvent { name: "Storage withdraw", format: "Storage withdraw: account: {account}, amount {amount}" },
+    AbiEvent { name: "Set approvers", format: "Set approvers: threshold: {threshold}, approvers: {approvers}" },
+    AbiEvent { name: "Propose withdrawal", format: "Propose withdrawal: proposal_id: {proposal_id}, account: {account}, amount {amount}, receiver: {receiver}" },
+    AbiEvent { name: "Propose approvers", format: "Propose approvers: proposal_id: {proposal_id}, account: {account}, threshold: {threshold}, approvers: {approvers}" },
+    AbiEvent { name: "Approve proposal", format: "Approve proposal: proposal_id: {proposal_id}, account: {account}" },
+    AbiEvent { name: "Cancel proposal", format: "Cancel proposal: proposal_id: {proposal_id}, account: {account}" },
+    AbiEvent { name: "Migrate", format: "Migrate: payments: {payments}, remaining {remaining}" },
+];
+
+/// Describes a method and checks, by coercing it to a function pointer, that the
+/// listed argument and result types are still the ones `PaymentShop` takes.
+macro_rules! method {
+    ($gen:ident, init $name:ident($($arg:ident: $ty:ty),*)) => {{
+        let _: fn($($ty),*) -> PaymentShop = PaymentShop::$name;
+        method!(@describe $gen, Init, None, $name($($arg: $ty),*))
+    }};
+    ($gen:ident, call $deposit:ident $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?) => {{
+        let _: fn(&mut PaymentShop, $($ty),*) $(-> $ret)? = PaymentShop::$name;
+        method!(@describe $gen, Call, $deposit, $name($($arg: $ty),*) $(-> $ret)?)
+    }};
+    ($gen:ident, view $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty) => {{
+        let _: fn(&PaymentShop, $($ty),*) -> $ret = PaymentShop::$name;
+        method!(@describe $gen, View, None, $name($($arg: $ty),*) -> $ret)
+    }};
+    (@describe $gen:ident, $kind:ident, $deposit:ident, $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?) => {
+        AbiMethod {
+            name: stringify!($name),
+            kind: MethodKind::$kind,
+            deposit: Deposit::$deposit,
+            args: vec![$(AbiArg {
+                name: stringify!($arg),
+                required: <$ty as AbiType>::REQUIRED,
+                schema: $gen.subschema_for::<<$ty as AbiType>::Schema>(),
+            }),*],
+            returns: None$(.or_else(|| Some($gen.subschema_for::<<$ret as AbiType>::Schema>())))?,
+        }
+    };
+}
+
+pub fn methods(gen: &mut SchemaGenerator) -> Vec<AbiMethod> {
+    vec![
+        method!(gen, init new(owner_id: AccountId, payment_fee_percent: U128)),
+        method!(gen, init migrate(total_payment_withdraw: U128)),
+        method!(gen, call OneYocto migrate_batch(limit: u64) -> bool),
+        method!(gen, call Storage req_payment(order_id: U128, user_id: Option<AccountId>, msg: String, fee: U128, max_uses: Option<u64>, metadata: Option<PaymentMetadata>, tip_receiver: Option<AccountId>, auto_confirm_after: Option<U64>)),
+        method!(gen, call Storage req_payments(requests: Vec<PaymentRequest>) -> Vec<U128>),
+        method!(gen, call Payment pay(pay_id: U128, tip: Option<U128>)),
+        method!(gen, call Payment pay_for(pay_id: U128, tip: Option<U128>)),
+        method!(gen, call Payment pay_with_coupon(pay_id: U128, code: String, tip: Option<U128>)),
+        method!(gen, call Payment pay_many(pay_ids: Vec<U128>)),
+        method!(gen, call OneYocto confirm(pay_id: U128)),
+        method!(gen, call Storage mark_shipped(pay_id: U128, tracking_ref: String)),
+        method!(gen, call OneYocto mark_delivered(pay_id: U128)),
+        method!(gen, call OneYocto dispute(pay_id: U128)),
+        method!(gen, call OneYocto claim(pay_id: U128)),
+        method!(gen, call OneYocto claim_many(pay_ids: Vec<U128>)),
+        method!(gen, call OneYocto claim_all(limit: u64, from_index: Option<U64>)),
+        method!(gen, call OneYocto refund(pay_id: U128)),
+        method!(gen, call OneYocto archive_payments(pay_ids: Vec<U128>)),
+        method!(gen, call OneYocto withdraw()),
+        method!(gen, call OneYocto set_payment_fee(payment_fee_percent: U128)),
+        method!(gen, call OneYocto set_tip_fee(tip_fee_percent: U128)),
+        method!(gen, call OneYocto set_approvers(approvers: Vec<AccountId>, threshold: u64)),
+        method!(gen, call OneYocto propose_withdrawal(amount: U128, receiver: AccountId) -> U64),
+        method!(gen, call OneYocto propose_approvers(approvers: Vec<AccountId>, threshold: u64) -> U64),
+        method!(gen, call OneYocto approve_proposal(proposal_id: U64)),
+        method!(gen, call OneYocto cancel_proposal(proposal_id: U64)),
+        method!(gen, call Storage create_coupon(code: String, discount: Discount, max_uses: u64, expires_at: Option<U64>, per_user_limit: Option<u64>)),
+        method!(gen, call StorageBalance storage_deposit(account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance),
+        method!(gen, call OneYocto storage_withdraw(amount: Option<U128>) -> StorageBalance),
+        method!(gen, view get_payment_info(pay_id: U128) -> Option<PaymentJson>),
+        method!(gen, view get_payment_history(pay_id: U128) -> Option<Vec<StatusChangeJson>>),
+        method!(gen, view get_payment_shop_info() -> PaymentShopJson),
+        method!(gen, view get_payid_from_orderid(order_id: U128) -> Option<U128>),
+        method!(gen, view get_shop_stats(shop: AccountId) -> PaymentStatsJson),
+        method!(gen, view get_platform_stats() -> PaymentStatsJson),
+        method!(gen, view get_balance_breakdown() -> BalanceBreakdownJson),
+        method!(gen, view get_coupon(shop: AccountId, code: String) -> Option<CouponJson>),
+        method!(gen, view get_proposal(proposal_id: U64) -> Option<ProposalJson>),
+        method!(gen, view get_approvers() -> ApproversJson),
+        method!(gen, view storage_balance_of(account_id: ValidAccountId) -> Option<StorageBalance>),
+        method!(gen, view storage_balance_bounds() -> StorageBalanceBounds),
+    ]
+}
+
+/// The whole interface, as checked in at `abi/payment_shop.json`.
+pub fn contract_abi() -> Value {
+    let mut gen = SchemaGenerator::default();
+    let methods = methods(&mut gen);
+
+    serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "methods": methods,
+        "events": EVENTS,
+        "definitions": gen.take_definitions(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABI_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/abi/payment_shop.json");
+
+    #[test]
+    fn test_abi_up_to_date() {
+        let abi = contract_abi();
+        if std::env::var("UPDATE_ABI").is_ok() {
+            std::fs::write(ABI_PATH, serde_json::to_string_pretty(&abi).unwrap() + "\n").unwrap();
+            return;
+        }
+
+        // compared as values, so key order does not matter
+        let checked_in: Value = serde_json::from_str(&std::fs::read_to_string(ABI_PATH).unwrap()).unwrap();
+        assert!(checked_in == abi, "abi/payment_shop.json is out of date, regenerate it with `UPDATE_ABI=1 cargo test --features abi test_abi_up_to_date`");
+    }
+
+    #[test]
+    fn test_optional_args() {
+        let methods = methods(&mut SchemaGenerator::default());
+        let pay = methods.iter().find(|method| method.name == "pay").unwrap();
+        assert_eq!(pay.deposit, Deposit::Payment);
+        assert_eq!(pay.args.iter().map(|arg| (arg.name, arg.required)).collect::<Vec<_>>(), vec![("pay_id", true), ("tip", false)]);
+        assert!(pay.returns.is_none());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::*;
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatusEscrowJson {
+    pub status: Status,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub amount: U128,
+}
+
+/// What the contract balance is made of. `balance` equals `escrow + pending_fees +
+/// storage_locked + storage_available + surplus - shortfall`, and one of `surplus`
+/// and `shortfall` is always zero.
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct BalanceBreakdownJson {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub balance: U128,
+    /// Held for paid payments until they are claimed or refunded.
+    pub escrow_by_status: Vec<StatusEscrowJson>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub escrow: U128,
+    /// Platform fees taken by claims and not withdrawn yet.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub pending_fees: U128,
+    /// Locked by the protocol for the contract's state, code included.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub storage_locked: U128,
+    /// Storage balances accounts can still withdraw.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub storage_available: U128,
+    /// More than the above explains, e.g. the deployer's funding or plain transfers.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub surplus: U128,
+    /// Less than the above requires. Anything but zero is a bug.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub shortfall: U128,
+}
+
+#[near_bindgen]
+impl PaymentShop {
+    pub fn get_balance_breakdown(&self) -> BalanceBreakdownJson {
+        let balance = env::account_balance();
+        let escrow = self.platform_stats.escrow();
+        let pending_fees = self.total_payment - self.total_payment_withdraw;
+        let storage_locked = env::storage_byte_cost() * Balance::from(env::storage_usage());
+
+        let explained = escrow + pending_fees + storage_locked + self.storage_available;
+        BalanceBreakdownJson {
+            balance: U128(balance),
+            escrow_by_status: self.platform_stats.by_status
+                .iter()
+                .filter(|counter| counter.status.holds_funds())
+                .map(|counter| StatusEscrowJson { status: counter.status.clone(), amount: U128(counter.escrow) })
+                .collect(),
+            escrow: U128(escrow),
+            pending_fees: U128(pending_fees),
+            storage_locked: U128(storage_locked),
+            storage_available: U128(self.storage_available),
+            surplus: U128(balance.saturating_sub(explained)),
+            shortfall: U128(explained.saturating_sub(balance)),
+        }
+    }
+}
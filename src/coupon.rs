@@ -0,0 +1,135 @@
+use crate::*;
+use crate::errors::*;
+use near_sdk::json_types::U64;
+
+pub const MAX_COUPON_CODE_LENGTH: usize = 32;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub enum Discount {
+    /// Share of the fee, in the same units as `payment_fee_percent`.
+    Percent(#[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))] U128),
+    /// Fixed amount in yoctoNEAR, capped at the fee.
+    Fixed(#[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))] U128),
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct Coupon {
+    pub discount: Discount,
+    pub max_uses: u64,
+    pub uses: u64,
+    pub expires_at: Option<u64>,
+    pub per_user_limit: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponJson {
+    pub shop: AccountId,
+    pub code: String,
+    pub discount: Discount,
+    pub max_uses: u64,
+    pub uses: u64,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub expires_at: Option<U64>,
+    pub per_user_limit: Option<u64>,
+}
+
+fn coupon_key(shop_id: &AccountId, code: &str) -> String {
+    format!("{}:{}", shop_id, code)
+}
+
+fn coupon_user_key(shop_id: &AccountId, code: &str, account_id: &AccountId) -> String {
+    format!("{}:{}:{}", shop_id, code, account_id)
+}
+
+#[near_bindgen]
+impl PaymentShop {
+    #[payable]
+    pub fn create_coupon(&mut self, code: String, discount: Discount, max_uses: u64, expires_at: Option<U64>, per_user_limit: Option<u64>) {
+        assert_at_least_one_yocto();
+        let shop_id = env::predecessor_account_id();
+
+        require(!code.is_empty() && code.len() <= MAX_COUPON_CODE_LENGTH, ContractError::InvalidCoupon);
+        // codes go unquoted into log lines and storage keys
+        require(code.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'), ContractError::InvalidCoupon);
+        require(max_uses > 0 && per_user_limit != Some(0), ContractError::InvalidCoupon);
+        if let Discount::Percent(percent) = &discount {
+            require(percent.0 > 0 && percent.0 <= DECIMALS as u128, ContractError::InvalidCoupon);
+        }
+
+        let key = coupon_key(&shop_id, &code);
+        require(self.coupons.get(&key).is_none(), ContractError::CouponExists);
+
+        let storage_use_before = env::storage_usage();
+        self.coupons.insert(&key, &Coupon {
+            discount,
+            max_uses,
+            uses: 0,
+            expires_at: expires_at.map(|expires_at| expires_at.0),
+            per_user_limit
+        });
+
+        let storage_use_after = env::storage_usage();
+        self.internal_charge_storage(&shop_id, storage_use_after - storage_use_before);
+
+        let log_message = format!("Create coupon: shop: {}, code: {}, max_uses: {}", shop_id, code, max_uses);
+        env::log(log_message.as_bytes());
+    }
+
+    #[payable]
+    pub fn pay_with_coupon(&mut self, pay_id: U128, code: String, tip: Option<U128>) {
+        assert_at_least_one_yocto();
+
+        let fee = env::attached_deposit();
+        let account_id = env::predecessor_account_id();
+
+        let storage_use_before = env::storage_usage();
+        let (payment_fee, history_storage) = self.internal_pay(pay_id.0, &account_id, fee, PayOptions { coupon: Some(code), tip: tip.map_or(0, |tip| tip.0), ..Default::default() });
+
+        let storage_use_after = env::storage_usage();
+        refund_unspent_deposit(payment_fee, storage_use_after - storage_use_before - history_storage);
+    }
+
+    pub fn get_coupon(&self, shop: AccountId, code: String) -> Option<CouponJson> {
+        self.coupons.get(&coupon_key(&shop, &code)).map(|coupon| CouponJson {
+            shop,
+            code,
+            discount: coupon.discount,
+            max_uses: coupon.max_uses,
+            uses: coupon.uses,
+            expires_at: coupon.expires_at.map(U64),
+            per_user_limit: coupon.per_user_limit
+        })
+    }
+}
+
+impl PaymentShop {
+    /// Uses coupon `code` of `shop_id` once for `account_id` and returns the discount on `fee`.
+    pub(crate) fn internal_redeem_coupon(&mut self, shop_id: &AccountId, code: &str, account_id: &AccountId, fee: Balance) -> Balance {
+        let key = coupon_key(shop_id, code);
+        let coupon = self.coupons.get(&key);
+        require(coupon.is_some(), ContractError::CouponNotFound);
+        let mut coupon = coupon.unwrap();
+
+        require(coupon.expires_at.map_or(true, |expires_at| env::block_timestamp() < expires_at), ContractError::CouponExpired);
+        require(coupon.uses < coupon.max_uses, ContractError::CouponUsedUp);
+
+        let user_key = coupon_user_key(shop_id, code, account_id);
+        let user_uses = self.coupon_uses.get(&user_key).unwrap_or(0);
+        require(coupon.per_user_limit.map_or(true, |limit| user_uses < limit), ContractError::CouponUserLimit);
+
+        coupon.uses += 1;
+        self.coupons.insert(&key, &coupon);
+        if coupon.per_user_limit.is_some() {
+            self.coupon_uses.insert(&user_key, &(user_uses + 1));
+        }
+
+        match coupon.discount {
+            Discount::Percent(percent) => fee * percent.0 / (DECIMALS as u128),
+            Discount::Fixed(amount) => std::cmp::min(amount.0, fee),
+        }
+    }
+}
@@ -1,23 +1,38 @@
 use crate::*;
 
 #[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
 #[serde(crate = "near_sdk::serde")]
 pub struct PaymentShopJson {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
     pub pay_id: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
     pub payment_fee_percent: U128,
-    pub total_payment: U128
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub total_payment: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub tip_fee_percent: U128
 }
 
 #[near_bindgen]
 impl PaymentShop {
-    pub fn get_payment_info(&self, pay_id: U128) -> PaymentJson {
-        let upgradable_payment = self.payments.get(&pay_id.0);
-        let payment;
-        match upgradable_payment {
-            Some(item) => payment = Payment::from(item),
-            None => payment = Payment::default()
-        };
-        PaymentJson::from(pay_id.0.clone(), payment)
+    pub fn get_payment_info(&self, pay_id: U128) -> Option<PaymentJson> {
+        match self.payments.get(&pay_id.0) {
+            Some(item) => Some(PaymentJson::from(pay_id.0, Payment::from(item))),
+            None if pay_id.0 > 0 && pay_id.0 <= self.pay_id => Some(PaymentJson::from(pay_id.0, Payment::archived(pay_id.0))),
+            None => None
+        }
+    }
+
+    /// Every status change of a stored payment, oldest first. Archived payments have none left.
+    pub fn get_payment_history(&self, pay_id: U128) -> Option<Vec<StatusChangeJson>> {
+        self.payments.get(&pay_id.0).map(|item| {
+            Payment::from(item)
+                .history
+                .into_iter()
+                .map(StatusChangeJson::from)
+                .collect()
+        })
     }
 
     pub fn get_payment_shop_info(&self) -> PaymentShopJson {
@@ -25,16 +40,20 @@ impl PaymentShop {
         PaymentShopJson {
             pay_id: U128(self.pay_id),
             payment_fee_percent: U128(self.payment_fee_percent),
-            total_payment: U128(self.total_payment)
+            total_payment: U128(self.total_payment),
+            tip_fee_percent: U128(self.tip_fee_percent)
         }
     }
 
-    pub fn get_payid_from_orderid(&self, order_id: U128) -> U128 {
-        match self.order_ids.get(&order_id.0) {
-            Some(value) => {
-                U128(value)
-            },
-            None => U128(0)
-        }
+    pub fn get_payid_from_orderid(&self, order_id: U128) -> Option<U128> {
+        self.order_ids.get(&order_id.0).map(U128)
     }
-}
\ No newline at end of file
+
+    pub fn get_shop_stats(&self, shop: AccountId) -> PaymentStatsJson {
+        PaymentStatsJson::from(self.shop_stats.get(&shop).unwrap_or_default())
+    }
+
+    pub fn get_platform_stats(&self) -> PaymentStatsJson {
+        PaymentStatsJson::from(self.platform_stats.clone())
+    }
+}
@@ -0,0 +1,136 @@
+use crate::*;
+use std::fmt;
+
+/// Reasons a contract call can fail. Each variant has a stable code that is
+/// the first word of the panic message, optionally followed by details.
+#[derive(Debug, PartialEq)]
+pub enum ContractError {
+    PaymentNotFound,
+    OrderIdExists,
+    InvalidStatus,
+    AccessDenied,
+    NotOwner,
+    NoPayments,
+    NothingToWithdraw,
+    InvalidPaymentFee,
+    InvalidMaxUses,
+    MaxUsesNotAllowed,
+    PaymentLinkUsedUp,
+    OpenPaymentOnBehalf,
+    DepositAtLeastOneYocto,
+    DepositExactlyOneYocto,
+    NotRegistered,
+    StorageDepositTooLow,
+    StorageWithdrawTooHigh,
+    MessageTooLong,
+    MetadataTooLarge,
+    InvalidReferenceHash,
+    LineItemsMismatch,
+    InvalidCoupon,
+    CouponExists,
+    CouponNotFound,
+    CouponExpired,
+    CouponUsedUp,
+    CouponUserLimit,
+    InvalidTipFee,
+    InvalidTip,
+    InvalidAccountId,
+    InvalidAutoConfirm,
+    TrackingRefTooLong,
+    NotApprover,
+    ApproversSet,
+    InvalidApprovers,
+    MultisigRequired,
+    InvalidWithdrawal,
+    ProposalNotFound,
+    ProposalExpired,
+    AlreadyApproved,
+    StateNotFound,
+    InvalidTotalWithdraw,
+    MigrationPending,
+    NoMigration,
+    InsufficientFee { required: Balance },
+    InsufficientStorageDeposit { required: Balance },
+    InsufficientStorageBalance { required: Balance },
+}
+
+impl ContractError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ContractError::PaymentNotFound => "ERR_PAYMENT_NOT_FOUND",
+            ContractError::OrderIdExists => "ERR_ORDER_ID_EXISTS",
+            ContractError::InvalidStatus => "ERR_INVALID_STATUS",
+            ContractError::AccessDenied => "ERR_ACCESS_DENIED",
+            ContractError::NotOwner => "ERR_NOT_OWNER",
+            ContractError::NoPayments => "ERR_NO_PAYMENTS",
+            ContractError::NothingToWithdraw => "ERR_NOTHING_TO_WITHDRAW",
+            ContractError::InvalidPaymentFee => "ERR_INVALID_PAYMENT_FEE",
+            ContractError::InvalidMaxUses => "ERR_INVALID_MAX_USES",
+            ContractError::MaxUsesNotAllowed => "ERR_MAX_USES_NOT_ALLOWED",
+            ContractError::PaymentLinkUsedUp => "ERR_PAYMENT_LINK_USED_UP",
+            ContractError::OpenPaymentOnBehalf => "ERR_OPEN_PAYMENT_ON_BEHALF",
+            ContractError::DepositAtLeastOneYocto => "ERR_DEPOSIT_AT_LEAST_ONE_YOCTO",
+            ContractError::DepositExactlyOneYocto => "ERR_DEPOSIT_EXACTLY_ONE_YOCTO",
+            ContractError::NotRegistered => "ERR_NOT_REGISTERED",
+            ContractError::StorageDepositTooLow => "ERR_STORAGE_DEPOSIT_TOO_LOW",
+            ContractError::StorageWithdrawTooHigh => "ERR_STORAGE_WITHDRAW_TOO_HIGH",
+            ContractError::MessageTooLong => "ERR_MESSAGE_TOO_LONG",
+            ContractError::MetadataTooLarge => "ERR_METADATA_TOO_LARGE",
+            ContractError::InvalidReferenceHash => "ERR_INVALID_REFERENCE_HASH",
+            ContractError::LineItemsMismatch => "ERR_LINE_ITEMS_MISMATCH",
+            ContractError::InvalidCoupon => "ERR_INVALID_COUPON",
+            ContractError::CouponExists => "ERR_COUPON_EXISTS",
+            ContractError::CouponNotFound => "ERR_COUPON_NOT_FOUND",
+            ContractError::CouponExpired => "ERR_COUPON_EXPIRED",
+            ContractError::CouponUsedUp => "ERR_COUPON_USED_UP",
+            ContractError::CouponUserLimit => "ERR_COUPON_USER_LIMIT",
+            ContractError::InvalidTipFee => "ERR_INVALID_TIP_FEE",
+            ContractError::InvalidTip => "ERR_INVALID_TIP",
+            ContractError::InvalidAccountId => "ERR_INVALID_ACCOUNT_ID",
+            ContractError::InvalidAutoConfirm => "ERR_INVALID_AUTO_CONFIRM",
+            ContractError::TrackingRefTooLong => "ERR_TRACKING_REF_TOO_LONG",
+            ContractError::NotApprover => "ERR_NOT_APPROVER",
+            ContractError::ApproversSet => "ERR_APPROVERS_SET",
+            ContractError::InvalidApprovers => "ERR_INVALID_APPROVERS",
+            ContractError::MultisigRequired => "ERR_MULTISIG_REQUIRED",
+            ContractError::InvalidWithdrawal => "ERR_INVALID_WITHDRAWAL",
+            ContractError::ProposalNotFound => "ERR_PROPOSAL_NOT_FOUND",
+            ContractError::ProposalExpired => "ERR_PROPOSAL_EXPIRED",
+            ContractError::AlreadyApproved => "ERR_ALREADY_APPROVED",
+            ContractError::StateNotFound => "ERR_STATE_NOT_FOUND",
+            ContractError::InvalidTotalWithdraw => "ERR_INVALID_TOTAL_WITHDRAW",
+            ContractError::MigrationPending => "ERR_MIGRATION_PENDING",
+            ContractError::NoMigration => "ERR_NO_MIGRATION",
+            ContractError::InsufficientFee { .. } => "ERR_INSUFFICIENT_FEE",
+            ContractError::InsufficientStorageDeposit { .. } => "ERR_INSUFFICIENT_STORAGE_DEPOSIT",
+            ContractError::InsufficientStorageBalance { .. } => "ERR_INSUFFICIENT_STORAGE_BALANCE",
+        }
+    }
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContractError::InsufficientFee { required } => {
+                write!(f, "{}: required FEE deposit of at least {} yoctoNEAR", self.code(), required)
+            },
+            ContractError::InsufficientStorageDeposit { required } => {
+                write!(f, "{}: must attach {} yoctoNear to cover storage", self.code(), required)
+            },
+            ContractError::InsufficientStorageBalance { required } => {
+                write!(f, "{}: must have {} yoctoNear of available storage balance", self.code(), required)
+            },
+            _ => write!(f, "{}", self.code())
+        }
+    }
+}
+
+pub(crate) fn panic_with(error: ContractError) -> ! {
+    env::panic(error.to_string().as_bytes())
+}
+
+pub(crate) fn require(condition: bool, error: ContractError) {
+    if !condition {
+        panic_with(error)
+    }
+}
@@ -0,0 +1,334 @@
+use crate::*;
+
+/// How `internal_pay` should treat a payment beyond the plain `pay` flow.
+#[derive(Default)]
+pub(crate) struct PayOptions {
+    /// Any account may fund a payment that already has a user.
+    pub on_behalf: bool,
+    /// Coupon of the payment's shop to redeem.
+    pub coupon: Option<String>,
+    /// Paid on top of the invoice, to the payment's tip receiver or shop.
+    pub tip: Balance,
+}
+
+impl PaymentShop {
+    pub(crate) fn internal_get_payment(&self, pay_id: u128) -> Payment {
+        let upgrade_payment = self.payments.get(&pay_id);
+        require(upgrade_payment.is_some(), ContractError::PaymentNotFound);
+        if let Some(migration) = &self.migration {
+            require(pay_id < migration.next_pay_id || pay_id > migration.last_pay_id, ContractError::MigrationPending);
+        }
+
+        Payment::from(upgrade_payment.unwrap())
+    }
+
+    /// Stores a new REQUESTING payment for `shop_id` and returns its pay_id.
+    /// Storage is measured and charged by the caller, once per call.
+    pub(crate) fn internal_req_payment(&mut self, shop_id: &AccountId, request: PaymentRequest) -> u128 {
+        let pay_id_for_order = self.order_ids.get(&request.order_id.0);
+        require(pay_id_for_order.is_none(), ContractError::OrderIdExists);
+        if let Some(max_uses) = request.max_uses {
+            require(request.user_id.is_none(), ContractError::MaxUsesNotAllowed);
+            require(max_uses > 0, ContractError::InvalidMaxUses);
+        }
+        require(request.msg.len() <= MAX_MSG_LENGTH, ContractError::MessageTooLong);
+        if let Some(metadata) = &request.metadata {
+            metadata.assert_valid(request.fee.0);
+        }
+        if let Some(auto_confirm_after) = request.auto_confirm_after {
+            require(auto_confirm_after.0 > 0 && auto_confirm_after.0 <= MAX_AUTO_CONFIRM_AFTER, ContractError::InvalidAutoConfirm);
+        }
+        if let Some(tip_receiver) = &request.tip_receiver {
+            require(env::is_valid_account_id(tip_receiver.as_bytes()), ContractError::InvalidAccountId);
+        }
+        let pay_id = self.pay_id + 1;
+
+        let mut payment = Payment {
+            payment_id: pay_id,
+            order_id: request.order_id.0,
+            shop: shop_id.clone(),
+            user: request.user_id,
+            msg: request.msg,
+            fee: request.fee.0,
+            status: Status::REQUESTING,
+            max_uses: request.max_uses,
+            uses: 0,
+            parent_id: None,
+            payer: None,
+            paid_at: 0,
+            metadata: request.metadata,
+            discount: 0,
+            coupon: None,
+            tip: 0,
+            tip_receiver: request.tip_receiver,
+            auto_confirm_after: request.auto_confirm_after.map(|auto_confirm_after| auto_confirm_after.0),
+            tracking_ref: None,
+            shipped_at: 0,
+            delivered_at: 0,
+            history: vec![],
+            shop_storage: 0,
+            payer_storage: 0
+        };
+
+        let log_message = format!("Request payment: payment_id: {}, order_id: {}, account: {}, fee: {}, data: {}", &pay_id, &payment.order_id, payment.user.as_deref().unwrap_or(""), payment.fee, payment.msg);
+        let payment_fee = payment.fee;
+        self.internal_update_stats(shop_id, |stats| stats.record_status(None, &Status::REQUESTING, payment_fee));
+        let storage_use_before = env::storage_usage();
+        self.payments.insert(&pay_id, &UpgradePayment::from(payment.clone()));
+        self.order_ids.insert(&request.order_id.0, &pay_id);
+        // rewriting the count keeps the record at the size just measured
+        payment.shop_storage = env::storage_usage() - storage_use_before;
+        self.payments.insert(&pay_id, &UpgradePayment::from(payment));
+        self.internal_add_shop_payment(shop_id, pay_id);
+
+        self.pay_id = pay_id;
+        env::log(log_message.as_bytes());
+
+        pay_id
+    }
+
+    /// Marks a requested payment as paid by `account_id` out of `fee` and returns
+    /// the part of `fee` consumed by it, along with the storage its status history
+    /// took, which the payer is not charged for. The rest the record grows by is the
+    /// payer's share of it.
+    ///
+    /// An open request is bound to its first payer. A reusable payment link stays
+    /// REQUESTING and a child payment is spawned for every payer instead.
+    pub(crate) fn internal_pay(&mut self, pay_id: u128, account_id: &AccountId, fee: Balance, options: PayOptions) -> (Balance, StorageUsage) {
+        let mut payment = self.internal_get_payment(pay_id);
+        require(payment.status.next(Action::Pay).is_some(), ContractError::InvalidStatus);
+
+        let discount = match &options.coupon {
+            Some(code) => self.internal_redeem_coupon(&payment.shop, code, account_id, payment.fee),
+            None => 0
+        };
+        // a tip too large to add to the fee could never be attached anyway
+        let amount = (payment.fee - discount).checked_add(options.tip);
+        require(amount.is_some(), ContractError::InvalidTip);
+        let amount = amount.unwrap();
+        require(fee >= amount, ContractError::InsufficientFee { required: amount });
+
+        if options.on_behalf {
+            require(payment.user.is_some(), ContractError::OpenPaymentOnBehalf);
+            if payment.user.as_ref() != Some(account_id) {
+                payment.payer = Some(account_id.clone());
+            }
+        } else if payment.user.is_none() {
+            if let Some(max_uses) = payment.max_uses {
+                require(payment.uses < max_uses, ContractError::PaymentLinkUsedUp);
+                payment.uses += 1;
+                self.payments.insert(&pay_id, &UpgradePayment::from(payment.clone()));
+
+                payment = self.internal_spawn_link_payment(payment, account_id);
+            } else {
+                payment.user = Some(account_id.clone());
+            }
+        }
+
+        let mut log_message = format!("Pay: payment_id: {}", payment.payment_id);
+        if let Some(parent_id) = payment.parent_id {
+            log_message.push_str(&format!(", parent_id: {}", parent_id));
+        }
+        if let Some(payer) = &payment.payer {
+            log_message.push_str(&format!(", payer: {}", payer));
+        }
+        if let Some(code) = &options.coupon {
+            log_message.push_str(&format!(", coupon: {}, discount {}", code, discount));
+        }
+        if options.tip > 0 {
+            log_message.push_str(&format!(", tip {}", options.tip));
+        }
+
+        payment.discount = discount;
+        payment.tip = options.tip;
+        payment.coupon = options.coupon;
+        payment.paid_at = env::block_timestamp();
+        let history_storage = self.internal_transition(&mut payment, Action::Pay, account_id);
+        let storage_use_before = env::storage_usage();
+        self.payments.insert(&payment.payment_id, &UpgradePayment::from(payment.clone()));
+        // rewriting the count keeps the record at the size just measured
+        payment.payer_storage += env::storage_usage() - storage_use_before - history_storage;
+        self.payments.insert(&payment.payment_id, &UpgradePayment::from(payment));
+
+        env::log(log_message.as_bytes());
+
+        (amount, history_storage)
+    }
+
+    /// Creates the REQUESTING child payment of a reusable payment link for `account_id`.
+    fn internal_spawn_link_payment(&mut self, link: Payment, account_id: &AccountId) -> Payment {
+        let pay_id = self.pay_id + 1;
+
+        let payment = Payment {
+            payment_id: pay_id,
+            user: Some(account_id.clone()),
+            max_uses: None,
+            uses: 0,
+            parent_id: Some(link.payment_id),
+            shop_storage: 0,
+            payer_storage: 0,
+            ..link
+        };
+
+        let payment_fee = payment.fee;
+        self.internal_update_stats(&payment.shop, |stats| stats.record_status(None, &Status::REQUESTING, payment_fee));
+        self.internal_add_shop_payment(&payment.shop, pay_id);
+        self.pay_id = pay_id;
+
+        payment
+    }
+
+    /// Confirms a payment for `account_id` through `action`, recording how long it took.
+    pub(crate) fn internal_confirm(&mut self, payment: &mut Payment, action: Action, account_id: &AccountId) {
+        let paid_at = payment.paid_at;
+        self.internal_transition(payment, action, account_id);
+        // payments paid before the upgrade did not record when
+        if paid_at > 0 {
+            let confirm_time = env::block_timestamp() - paid_at;
+            self.internal_update_stats(&payment.shop, |stats| stats.record_confirm_time(confirm_time));
+        }
+    }
+
+    /// Marks a paid payment as refunded and returns who funded it with the amount owed back.
+    pub(crate) fn internal_refund(&mut self, pay_id: u128, account_id: &AccountId) -> (AccountId, Balance) {
+        let mut payment = self.internal_get_payment(pay_id);
+        self.internal_transition(&mut payment, Action::Refund, account_id);
+
+        let refund_receiver = payment.payer.clone().or_else(|| payment.user.clone()).unwrap();
+        let mut refund_amount = payment.amount();
+
+        self.internal_update_stats(&payment.shop, |stats| stats.total_refunded += refund_amount);
+        let (history_storage, history_cost) = pay_history_storage(&payment, &mut refund_amount);
+        payment.payer_storage += history_storage;
+        let shop_id = payment.shop.clone();
+        self.payments.insert(&pay_id, &UpgradePayment::from(payment));
+        self.internal_remove_shop_payment(&shop_id, pay_id);
+
+        let mut log_message = format!("Refund: payment_id: {}, account: {}, amount {}", pay_id, refund_receiver, refund_amount);
+        if history_cost > 0 {
+            log_message.push_str(&format!(", storage {}", history_cost));
+        }
+        env::log(log_message.as_bytes());
+
+        (refund_receiver, refund_amount)
+    }
+
+    /// Removes a finalized payment as `account_id`, logging its last state, and gives
+    /// its shop and payer back the storage each paid for the record.
+    pub(crate) fn internal_archive(&mut self, pay_id: u128, account_id: &AccountId) {
+        let payment = self.internal_get_payment(pay_id);
+        // the payment is removed rather than stored as ARCHIVED, so only the transition is checked
+        let actor = Actor::resolve(&payment, account_id, &self.owner_id);
+        payment.status.transition(Action::Archive, actor).unwrap_or_else(|err| panic_with(err));
+
+        let storage_use_before = env::storage_usage();
+        self.payments.remove(&pay_id);
+        if self.order_ids.get(&payment.order_id) == Some(pay_id) {
+            self.order_ids.remove(&payment.order_id);
+        }
+
+        // what neither paid for was kept at the contract's expense
+        let storage_freed = storage_use_before - env::storage_usage();
+        let shop_storage = std::cmp::min(storage_freed, payment.shop_storage);
+        self.internal_release_storage(&payment.shop, shop_storage);
+        if let Some(payer) = payment.payer.clone().or_else(|| payment.user.clone()) {
+            self.internal_release_storage(&payer, std::cmp::min(storage_freed - shop_storage, payment.payer_storage));
+        }
+
+        let log_message = format!("Archive payment: {}", near_sdk::serde_json::to_string(&PaymentJson::from(pay_id, payment)).unwrap());
+        env::log(log_message.as_bytes());
+    }
+
+    pub(crate) fn internal_add_shop_payment(&mut self, shop_id: &AccountId, pay_id: u128) {
+        let mut shop_payments = self.shop_payments.get(shop_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::ShopPaymentsInnerKey {
+                account_hash: env::sha256(shop_id.as_bytes())
+            })
+        });
+        shop_payments.insert(&pay_id);
+        self.shop_payments.insert(shop_id, &shop_payments);
+    }
+
+    pub(crate) fn internal_remove_shop_payment(&mut self, shop_id: &AccountId, pay_id: u128) {
+        if let Some(mut shop_payments) = self.shop_payments.get(shop_id) {
+            shop_payments.remove(&pay_id);
+            if shop_payments.is_empty() {
+                self.shop_payments.remove(shop_id);
+            } else {
+                self.shop_payments.insert(shop_id, &shop_payments);
+            }
+        }
+    }
+
+    /// Marks a confirmed payment as claimed and returns the amount owed to the shop,
+    /// plus the tip owed to a designated tip receiver if there is one.
+    /// The transfers themselves are left to the caller so several claims can share one promise.
+    pub(crate) fn internal_claim(&mut self, pay_id: u128, account_id: &AccountId) -> (Balance, Option<(AccountId, Balance)>) {
+        let mut payment = self.internal_get_payment(pay_id);
+        if payment.is_awaiting_confirmation() && payment.is_claimable(env::block_timestamp()) {
+            self.internal_confirm(&mut payment, Action::AutoConfirm, account_id);
+
+            let log_message = format!("Auto confirm: payment_id: {}", pay_id);
+            env::log(log_message.as_bytes());
+        }
+
+        let payment_amount = payment.fee - payment.discount;
+        let payment_fee_amount = payment_amount * self.payment_fee_percent / (DECIMALS as u128);
+        let tip_fee_amount = payment.tip * self.tip_fee_percent / (DECIMALS as u128);
+        let tip_amount = payment.tip - tip_fee_amount;
+
+        let (mut payment_recever, tip_payout) = match &payment.tip_receiver {
+            Some(tip_receiver) if tip_amount > 0 => (payment_amount - payment_fee_amount, Some((tip_receiver.clone(), tip_amount))),
+            _ => (payment_amount - payment_fee_amount + tip_amount, None)
+        };
+        let total_fee_amount = payment_fee_amount + tip_fee_amount;
+
+        self.internal_transition(&mut payment, Action::Claim, account_id);
+        let (history_storage, history_cost) = pay_history_storage(&payment, &mut payment_recever);
+        payment.shop_storage += history_storage;
+        self.internal_update_stats(&payment.shop, |stats| stats.total_fees += total_fee_amount);
+        self.payments.insert(&pay_id, &UpgradePayment::from(payment));
+        self.internal_remove_shop_payment(account_id, pay_id);
+
+        self.total_payment += total_fee_amount;
+
+        let mut log_message = format!("Shop claim: payment_id: {}, amount {}", pay_id, payment_recever);
+        if history_cost > 0 {
+            log_message.push_str(&format!(", storage {}", history_cost));
+        }
+        if let Some((tip_receiver, tip_amount)) = &tip_payout {
+            log_message.push_str(&format!(", tip_receiver: {}, tip {}", tip_receiver, tip_amount));
+        }
+        env::log(log_message.as_bytes());
+
+        (payment_recever, tip_payout)
+    }
+
+    pub(crate) fn internal_claim_many(&mut self, account_id: &AccountId, pay_ids: Vec<u128>) {
+        let mut total_recever: Balance = 0;
+        for pay_id in pay_ids.iter() {
+            let (payment_recever, tip_payout) = self.internal_claim(*pay_id, account_id);
+            total_recever += payment_recever;
+            if let Some((tip_receiver, tip_amount)) = tip_payout {
+                Promise::new(tip_receiver).transfer(tip_amount);
+            }
+        }
+
+        Promise::new(account_id.clone()).transfer(total_recever);
+
+        let log_message = format!("Shop claim many: count: {}, amount {}", pay_ids.len(), total_recever);
+        env::log(log_message.as_bytes());
+    }
+}
+
+/// Pays for the status history of a settled `payment` out of `payout`, as far as it covers
+/// it. Returns the bytes paid for, which archiving gives back to whoever `payout` was for,
+/// and what they cost.
+fn pay_history_storage(payment: &Payment, payout: &mut Balance) -> (StorageUsage, Balance) {
+    let byte_cost = env::storage_byte_cost();
+    let history_storage = std::cmp::min(Balance::from(payment.history_storage()), *payout / byte_cost);
+    let history_cost = byte_cost * history_storage;
+    *payout -= history_cost;
+
+    (history_storage as StorageUsage, history_cost)
+}
@@ -1,24 +1,67 @@
-use near_sdk::json_types::U128;
-use near_sdk::{env, AccountId, Balance, near_bindgen, PanicOnDefault, BorshStorageKey, Promise};
-use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, AccountId, Balance, StorageUsage, near_bindgen, PanicOnDefault, BorshStorageKey, Promise};
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
 use near_sdk::borsh::{self, BorshSerialize, BorshDeserialize};
 use near_sdk::serde::{Deserialize, Serialize};
 
 use crate::util::*;
+use crate::errors::*;
 use crate::payment::*;
+use crate::storage::*;
+use crate::stats::*;
+use crate::metadata::*;
+use crate::coupon::*;
+use crate::transition::*;
+use crate::treasury::*;
+use crate::upgrade::Migration;
+use crate::internal::PayOptions;
 pub use crate::enumeration::*;
+pub use crate::errors::ContractError;
 pub use crate::payment::PaymentJson;
+pub use crate::payment::PaymentRequest;
 pub use crate::payment::Status;
+pub use crate::storage::{StorageBalance, StorageBalanceBounds};
+pub use crate::stats::{PaymentStatsJson, StatusCounterJson};
+pub use crate::metadata::{LineItem, PaymentMetadata};
+pub use crate::coupon::{CouponJson, Discount};
+pub use crate::transition::{Action, Actor, StatusChange, StatusChangeJson};
+pub use crate::balance::{BalanceBreakdownJson, StatusEscrowJson};
+pub use crate::treasury::{ApproversJson, ProposalAction, ProposalJson};
 
 mod util;
+mod errors;
 mod payment;
 mod enumeration;
+mod internal;
+mod storage;
+mod stats;
+mod metadata;
+mod coupon;
+mod transition;
+mod balance;
+mod treasury;
+mod upgrade;
+#[cfg(feature = "abi")]
+pub mod abi;
+#[cfg(test)]
+mod tests;
+#[cfg(test)]
+mod proptests;
 
 const DECIMALS: u32 = 100000;
+/// Longest delivery window a shop can set before a paid payment confirms itself: 30 days.
+const MAX_AUTO_CONFIRM_AFTER: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
 
 #[derive(BorshDeserialize, BorshSerialize, BorshStorageKey)]
 pub enum StorageKey {
-    PayIdKey
+    PayIdKey,
+    ShopPaymentsKey,
+    ShopPaymentsInnerKey { account_hash: Vec<u8> },
+    StorageAccountsKey,
+    ShopStatsKey,
+    CouponsKey,
+    CouponUsesKey,
+    ProposalsKey
 }
 
 #[near_bindgen]
@@ -29,74 +72,145 @@ pub struct PaymentShop {
     pub payment_fee_percent: u128,
     pub total_payment: u128,
     pub total_payment_withdraw: u128,
+    pub tip_fee_percent: u128,
     pub payments: LookupMap<u128, UpgradePayment>,
-    pub order_ids: UnorderedMap<u128, u128>
+    pub order_ids: UnorderedMap<u128, u128>,
+    pub shop_payments: LookupMap<AccountId, UnorderedSet<u128>>,
+    pub storage_accounts: LookupMap<AccountId, StorageAccount>,
+    pub shop_stats: LookupMap<AccountId, PaymentStats>,
+    pub platform_stats: PaymentStats,
+    pub coupons: LookupMap<String, Coupon>,
+    pub coupon_uses: LookupMap<String, u64>,
+    /// Sum of the available storage balances, which accounts can withdraw at any time.
+    pub storage_available: Balance,
+    /// Accounts that approve withdrawals once set, `approval_threshold` of them per proposal.
+    pub approvers: Vec<AccountId>,
+    pub approval_threshold: u64,
+    pub proposal_id: u64,
+    pub proposals: LookupMap<u64, Proposal>,
+    /// Set while payments of the first deployed version still wait for `migrate_batch`.
+    pub migration: Option<Migration>
 }
 
 #[near_bindgen]
 impl PaymentShop {
     #[init]
     pub fn new(owner_id: AccountId, payment_fee_percent: U128) -> Self {
+        require(payment_fee_percent.0 <= DECIMALS as u128, ContractError::InvalidPaymentFee);
+
         PaymentShop {
             owner_id,
             pay_id: 0,
             payment_fee_percent: payment_fee_percent.0,
             total_payment: 0,
             total_payment_withdraw: 0,
+            tip_fee_percent: 0,
             payments: LookupMap::new(StorageKey::PayIdKey),
-            order_ids: UnorderedMap::new(b"m")
+            order_ids: UnorderedMap::new(b"m"),
+            shop_payments: LookupMap::new(StorageKey::ShopPaymentsKey),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccountsKey),
+            shop_stats: LookupMap::new(StorageKey::ShopStatsKey),
+            platform_stats: PaymentStats::default(),
+            coupons: LookupMap::new(StorageKey::CouponsKey),
+            coupon_uses: LookupMap::new(StorageKey::CouponUsesKey),
+            storage_available: 0,
+            approvers: Vec::new(),
+            approval_threshold: 0,
+            proposal_id: 0,
+            proposals: LookupMap::new(StorageKey::ProposalsKey),
+            migration: None
         }
     }
 
     #[payable]
-    pub fn req_payment(&mut self, order_id: U128, user_id: AccountId, msg: String, fee: U128) {
-        let pay_id_for_order = self.order_ids.get(&order_id.0);
-        assert!(pay_id_for_order.is_none(), "Order ID is set");
+    pub fn req_payment(&mut self, order_id: U128, user_id: Option<AccountId>, msg: String, fee: U128, max_uses: Option<u64>, metadata: Option<PaymentMetadata>, tip_receiver: Option<AccountId>, auto_confirm_after: Option<U64>) {
         assert_at_least_one_yocto();
         let shop_id = env::predecessor_account_id();
-        let pay_id = self.pay_id + 1;
-     
+
         let storage_use_before = env::storage_usage();
-        let payment = Payment {
-            payment_id: pay_id,
-            order_id: order_id.0,
-            shop: shop_id,
-            user: user_id,
-            msg: msg,
-            fee: fee.0,
-            status: Status::REQUESTING
-        }; 
-
-        let log_message = format!("Request payment: payment_id: {}, order_id: {}, account: {}, fee: {}, data: {}", &pay_id, &order_id.0, payment.user, payment.fee, payment.msg);
-        self.payments.insert(&pay_id, &UpgradePayment::from(payment));
-        self.order_ids.insert(&order_id.0, &pay_id);
+        self.internal_req_payment(&shop_id, PaymentRequest { order_id, user_id, msg, fee, max_uses, metadata, tip_receiver, auto_confirm_after });
 
         let storage_use_after = env::storage_usage();
-        refund_deposit(storage_use_after - storage_use_before);
-        self.pay_id = pay_id;
-        env::log(log_message.as_bytes());
+        self.internal_charge_storage(&shop_id, storage_use_after - storage_use_before);
+    }
+
+    #[payable]
+    pub fn req_payments(&mut self, requests: Vec<PaymentRequest>) -> Vec<U128> {
+        assert_at_least_one_yocto();
+        require(!requests.is_empty(), ContractError::NoPayments);
+        let shop_id = env::predecessor_account_id();
+
+        let storage_use_before = env::storage_usage();
+        let pay_ids: Vec<U128> = requests
+            .into_iter()
+            .map(|request| U128(self.internal_req_payment(&shop_id, request)))
+            .collect();
+
+        let storage_use_after = env::storage_usage();
+        self.internal_charge_storage(&shop_id, storage_use_after - storage_use_before);
+
+        pay_ids
+    }
+
+    #[payable]
+    pub fn pay(&mut self, pay_id: U128, tip: Option<U128>) {
+        assert_at_least_one_yocto();
+
+        let fee = env::attached_deposit();
+        let account_id = env::predecessor_account_id();
+
+        let storage_use_before = env::storage_usage();
+        let (payment_fee, history_storage) = self.internal_pay(pay_id.0, &account_id, fee, PayOptions { tip: tip.map_or(0, |tip| tip.0), ..Default::default() });
+
+        let storage_use_after = env::storage_usage();
+        refund_unspent_deposit(payment_fee, storage_use_after - storage_use_before - history_storage);
+    }
+
+    #[payable]
+    pub fn pay_for(&mut self, pay_id: U128, tip: Option<U128>) {
+        assert_at_least_one_yocto();
+
+        let fee = env::attached_deposit();
+        let account_id = env::predecessor_account_id();
+
+        let storage_use_before = env::storage_usage();
+        let (payment_fee, history_storage) = self.internal_pay(pay_id.0, &account_id, fee, PayOptions { on_behalf: true, tip: tip.map_or(0, |tip| tip.0), ..Default::default() });
+
+        let storage_use_after = env::storage_usage();
+        refund_unspent_deposit(payment_fee, storage_use_after - storage_use_before - history_storage);
     }
 
     #[payable]
-    pub fn pay(&mut self, pay_id: U128) {
+    pub fn pay_many(&mut self, pay_ids: Vec<U128>) {
         assert_at_least_one_yocto();
+        require(!pay_ids.is_empty(), ContractError::NoPayments);
 
         let fee = env::attached_deposit();
         let account_id = env::predecessor_account_id();
 
-        let upgrade_payment = self.payments.get(&pay_id.0);
-        assert!(upgrade_payment.is_some(), "ERR_PAYMENT_NOT_FOUND");
+        let total_fee: Balance = pay_ids
+            .iter()
+            .map(|pay_id| self.internal_get_payment(pay_id.0).fee)
+            .sum();
+        require(fee >= total_fee, ContractError::InsufficientFee { required: total_fee });
 
-        let mut payment = Payment::from(upgrade_payment.unwrap());
+        let storage_use_before = env::storage_usage();
+        let mut remaining_fee = fee;
+        let mut history_storage = 0;
+        for pay_id in pay_ids.iter() {
+            let (payment_fee, payment_history_storage) = self.internal_pay(pay_id.0, &account_id, remaining_fee, PayOptions::default());
+            remaining_fee -= payment_fee;
+            history_storage += payment_history_storage;
+        }
 
-        assert!(payment.status == Status::REQUESTING, "Invalid status");
-        assert!(fee >= payment.fee, "Required FEE deposit of at least {} yoctoNEAR", payment.fee);
-        assert_eq!(account_id, payment.user, "Access deny");
+        let storage_use_after = env::storage_usage();
+        remaining_fee -= assert_storage_deposit(remaining_fee, storage_use_after - storage_use_before - history_storage);
 
-        payment.status = Status::PAID;
-        self.payments.insert(&self.pay_id, &UpgradePayment::from(payment));
+        if remaining_fee > 0 {
+            Promise::new(account_id).transfer(remaining_fee);
+        }
 
-        let log_message = format!("Pay: payment_id: {}", self.pay_id);
+        let log_message = format!("Pay many: count: {}, amount {}, refund {}", pay_ids.len(), total_fee, remaining_fee);
         env::log(log_message.as_bytes());
     }
 
@@ -105,18 +219,62 @@ impl PaymentShop {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
 
-        let upgrade_payment = self.payments.get(&pay_id.0);
-        assert!(upgrade_payment.is_some(), "ERR_PAYMENT_NOT_FOUND");
+        let mut payment = self.internal_get_payment(pay_id.0);
+        self.internal_confirm(&mut payment, Action::Confirm, &account_id);
+        self.payments.insert(&pay_id.0, &UpgradePayment::from(payment));
+
+        let log_message = format!("Confirm: payment_id: {}", pay_id.0);
+        env::log(log_message.as_bytes());
+    }
+
+    #[payable]
+    pub fn mark_shipped(&mut self, pay_id: U128, tracking_ref: String) {
+        assert_at_least_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(tracking_ref.len() <= MAX_TRACKING_REF_LENGTH, ContractError::TrackingRefTooLong);
 
-        let mut payment = Payment::from(upgrade_payment.unwrap());
-        assert!(payment.status == Status::PAID, "Invalid status");
+        let mut payment = self.internal_get_payment(pay_id.0);
 
-        assert!(account_id == payment.user || account_id == self.owner_id, "Access deny");
+        let storage_use_before = env::storage_usage();
+        let history_storage = self.internal_transition(&mut payment, Action::Ship, &account_id);
+        payment.tracking_ref = Some(tracking_ref);
+        payment.shipped_at = env::block_timestamp();
+        self.payments.insert(&pay_id.0, &UpgradePayment::from(payment.clone()));
 
-        payment.status = Status::CONFIRMED;
-        self.payments.insert(&self.pay_id, &UpgradePayment::from(payment));
+        let storage_use_after = env::storage_usage();
+        let storage_used = storage_use_after - storage_use_before - history_storage;
+        payment.shop_storage += storage_used;
+        self.payments.insert(&pay_id.0, &UpgradePayment::from(payment));
+        self.internal_charge_storage(&account_id, storage_used);
 
-        let log_message = format!("Confirm: payment_id: {}", self.pay_id);
+        let log_message = format!("Ship: payment_id: {}", pay_id.0);
+        env::log(log_message.as_bytes());
+    }
+
+    #[payable]
+    pub fn mark_delivered(&mut self, pay_id: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let mut payment = self.internal_get_payment(pay_id.0);
+        self.internal_transition(&mut payment, Action::Deliver, &account_id);
+        payment.delivered_at = env::block_timestamp();
+        self.payments.insert(&pay_id.0, &UpgradePayment::from(payment));
+
+        let log_message = format!("Deliver: payment_id: {}", pay_id.0);
+        env::log(log_message.as_bytes());
+    }
+
+    #[payable]
+    pub fn dispute(&mut self, pay_id: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let mut payment = self.internal_get_payment(pay_id.0);
+        self.internal_transition(&mut payment, Action::Dispute, &account_id);
+        self.payments.insert(&pay_id.0, &UpgradePayment::from(payment));
+
+        let log_message = format!("Dispute: payment_id: {}", pay_id.0);
         env::log(log_message.as_bytes());
     }
 
@@ -124,56 +282,102 @@ impl PaymentShop {
     pub fn claim(&mut self, pay_id: U128) {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
-        
-        let upgrade_payment = self.payments.get(&pay_id.0);
-        assert!(upgrade_payment.is_some(), "ERR_PAYMENT_NOT_FOUND");
 
-        let mut payment = Payment::from(upgrade_payment.unwrap());
-        assert!(payment.status == Status::CONFIRMED, "Invalid status");
+        let (payment_recever, tip_payout) = self.internal_claim(pay_id.0, &account_id);
+        Promise::new(account_id).transfer(payment_recever);
+        if let Some((tip_receiver, tip_amount)) = tip_payout {
+            Promise::new(tip_receiver).transfer(tip_amount);
+        }
+    }
 
-        assert_eq!(account_id, payment.shop, "Access deny");
+    #[payable]
+    pub fn claim_many(&mut self, pay_ids: Vec<U128>) {
+        assert_one_yocto();
+        require(!pay_ids.is_empty(), ContractError::NoPayments);
+        let account_id = env::predecessor_account_id();
 
-        let payment_fee_amount = payment.fee * self.payment_fee_percent / (DECIMALS as u128);
-        let payment_recever = payment.fee - payment_fee_amount;
+        self.internal_claim_many(&account_id, pay_ids.iter().map(|pay_id| pay_id.0).collect());
+    }
 
-        payment.status = Status::CLAIMED;
-        let shop_id = payment.shop.clone();
-        self.payments.insert(&self.pay_id, &UpgradePayment::from(payment));
+    /// Claims whatever is claimable among `limit` of the caller's open payments, starting at
+    /// `from_index`. Claimed payments leave the set, so later calls see the rest.
+    #[payable]
+    pub fn claim_all(&mut self, limit: u64, from_index: Option<U64>) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
 
-        self.total_payment += payment_fee_amount;
-        Promise::new(shop_id).transfer(payment_recever);
+        let pay_ids: Vec<u128> = match self.shop_payments.get(&account_id) {
+            Some(shop_payments) => {
+                let start = from_index.map_or(0, |index| index.0);
+                let end = std::cmp::min(start.saturating_add(limit), shop_payments.len());
+                (start..end)
+                    .filter_map(|index| shop_payments.as_vector().get(index))
+                    .filter(|pay_id| self.internal_get_payment(*pay_id).is_claimable(env::block_timestamp()))
+                    .collect()
+            }
+            None => vec![]
+        };
+        require(!pay_ids.is_empty(), ContractError::NoPayments);
+
+        self.internal_claim_many(&account_id, pay_ids);
+    }
 
-        let log_message = format!("Shop claim: payment_id: {}, amount {}", self.pay_id, payment_recever);
-        env::log(log_message.as_bytes());
+    #[payable]
+    pub fn refund(&mut self, pay_id: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let (refund_receiver, refund_amount) = self.internal_refund(pay_id.0, &account_id);
+        Promise::new(refund_receiver).transfer(refund_amount);
     }
 
     #[payable]
-    pub fn withdraw(&mut self) {
+    pub fn archive_payments(&mut self, pay_ids: Vec<U128>) {
         assert_one_yocto();
+        require(!pay_ids.is_empty(), ContractError::NoPayments);
         let account_id = env::predecessor_account_id();
-        assert_eq!(account_id, self.owner_id, "Not admin or owner");
-        assert!(self.total_payment > 0, "No amount to withdraw");
 
-        let payment_withdraw = self.total_payment - self.total_payment_withdraw;
-        self.total_payment_withdraw = self.total_payment;
-        Promise::new(account_id).transfer(payment_withdraw);
+        for pay_id in pay_ids.iter() {
+            self.internal_archive(pay_id.0, &account_id);
+        }
+    }
 
-        self.total_payment_withdraw = payment_withdraw.clone();
 
-        let log_message = format!("Withdraw: amount {}", payment_withdraw);
-        env::log(log_message.as_bytes());
+    #[payable]
+    pub fn withdraw(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(account_id == self.owner_id, ContractError::NotOwner);
+        require(self.approvers.is_empty(), ContractError::MultisigRequired);
+        require(self.total_payment > self.total_payment_withdraw, ContractError::NothingToWithdraw);
+
+        let payment_withdraw = self.total_payment - self.total_payment_withdraw;
+        self.internal_withdraw(payment_withdraw, account_id, None);
     }
 
     #[payable]
     pub fn set_payment_fee(&mut self, payment_fee_percent: U128) {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
-        assert_eq!(account_id, self.owner_id, "Not admin or owner");
-        assert!(payment_fee_percent.0 > 0, "Invalid payment fee");
+        require(account_id == self.owner_id, ContractError::NotOwner);
+        require(payment_fee_percent.0 > 0 && payment_fee_percent.0 <= DECIMALS as u128, ContractError::InvalidPaymentFee);
 
         self.payment_fee_percent = payment_fee_percent.0;
 
         let log_message = format!("Set payment fee: {}", payment_fee_percent.0);
         env::log(log_message.as_bytes());
     }
-}
\ No newline at end of file
+
+    #[payable]
+    pub fn set_tip_fee(&mut self, tip_fee_percent: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(account_id == self.owner_id, ContractError::NotOwner);
+        require(tip_fee_percent.0 <= DECIMALS as u128, ContractError::InvalidTipFee);
+
+        self.tip_fee_percent = tip_fee_percent.0;
+
+        let log_message = format!("Set tip fee: {}", tip_fee_percent.0);
+        env::log(log_message.as_bytes());
+    }
+}
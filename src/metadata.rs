@@ -0,0 +1,58 @@
+use crate::*;
+use crate::errors::*;
+use near_sdk::json_types::Base64VecU8;
+
+pub const MAX_MSG_LENGTH: usize = 1024;
+pub const MAX_LINE_ITEMS: usize = 32;
+pub const MAX_SKU_LENGTH: usize = 64;
+pub const MAX_CURRENCY_LENGTH: usize = 8;
+pub const MAX_REFERENCE_LENGTH: usize = 256;
+pub const REFERENCE_HASH_LENGTH: usize = 32;
+pub const MAX_TRACKING_REF_LENGTH: usize = 128;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct LineItem {
+    pub sku: String,
+    pub qty: u32,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub unit_price: U128,
+}
+
+/// Structured invoice data attached to a payment request. Larger documents stay
+/// off-chain, referenced by `reference` and pinned by the sha256 `reference_hash`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentMetadata {
+    pub line_items: Vec<LineItem>,
+    pub currency: Option<String>,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub tax: Option<U128>,
+    pub reference: Option<String>,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<String>"))]
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+impl PaymentMetadata {
+    /// Checks the size limits and, when line items are given, that they add up
+    /// with the tax to `fee`.
+    pub fn assert_valid(&self, fee: Balance) {
+        require(self.line_items.len() <= MAX_LINE_ITEMS, ContractError::MetadataTooLarge);
+        require(self.line_items.iter().all(|item| item.sku.len() <= MAX_SKU_LENGTH), ContractError::MetadataTooLarge);
+        require(self.currency.as_ref().map_or(true, |currency| currency.len() <= MAX_CURRENCY_LENGTH), ContractError::MetadataTooLarge);
+        require(self.reference.as_ref().map_or(true, |reference| reference.len() <= MAX_REFERENCE_LENGTH), ContractError::MetadataTooLarge);
+        require(self.reference_hash.as_ref().map_or(true, |hash| hash.0.len() == REFERENCE_HASH_LENGTH), ContractError::InvalidReferenceHash);
+
+        if !self.line_items.is_empty() {
+            // totals that overflow cannot add up to any fee
+            let total = self.line_items
+                .iter()
+                .try_fold(self.tax.map_or(0, |tax| tax.0), |total: Balance, item| {
+                    Balance::from(item.qty).checked_mul(item.unit_price.0).and_then(|price| total.checked_add(price))
+                });
+            require(total == Some(fee), ContractError::LineItemsMismatch);
+        }
+    }
+}
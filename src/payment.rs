@@ -1,4 +1,5 @@
 use crate::*;
+use near_sdk::json_types::U64;
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "near_sdk::serde")]
@@ -6,23 +7,65 @@ pub struct Payment {
     pub payment_id: u128,
     pub order_id: u128,
     pub shop: AccountId,
-    pub user: AccountId,
+    pub user: Option<AccountId>,
     pub msg: String,
     pub fee: Balance,
     pub status: Status,
+    pub max_uses: Option<u64>,
+    pub uses: u64,
+    pub parent_id: Option<u128>,
+    pub payer: Option<AccountId>,
+    pub paid_at: u64,
+    pub metadata: Option<PaymentMetadata>,
+    pub discount: Balance,
+    pub coupon: Option<String>,
+    pub tip: Balance,
+    pub tip_receiver: Option<AccountId>,
+    pub auto_confirm_after: Option<u64>,
+    pub tracking_ref: Option<String>,
+    pub shipped_at: u64,
+    pub delivered_at: u64,
+    pub history: Vec<StatusChange>,
+    /// Storage of this payment's record paid by the shop, given back to it on archiving.
+    pub shop_storage: StorageUsage,
+    /// Storage of this payment's record paid by whoever funded it, given back to them
+    /// on archiving.
+    pub payer_storage: StorageUsage,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, PartialEq, Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
 #[serde(crate = "near_sdk::serde")]
 pub enum Status {
     REQUESTING,
     PAID,
     CONFIRMED,
     CLAIMED,
+    REFUNDED,
+    DISPUTED,
+    SHIPPED,
+    DELIVERED,
+    /// Only reported by views, for payments removed by `archive_payments`.
+    ARCHIVED,
+}
+
+/// A payment as stored by the first deployed version, before open payment requests.
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct PaymentV1 {
+    pub payment_id: u128,
+    pub order_id: u128,
+    pub shop: AccountId,
+    pub user: AccountId,
+    pub msg: String,
+    pub fee: Balance,
+    pub status: Status,
 }
 
+/// Stored payments. Variants are only ever appended, records of an older layout are
+/// converted when read and written back in the current one.
 #[derive(BorshDeserialize, BorshSerialize, Debug)]
 pub enum UpgradePayment {
+    V1(PaymentV1),
     Current(Payment),
 }
 
@@ -32,10 +75,105 @@ impl Default for Payment {
             payment_id: 0,
             order_id: 0,
             shop: "".to_string(),
-            user: "".to_string(),
+            user: None,
             msg: "".to_string(),
             fee: 0,
             status: Status::REQUESTING,
+            max_uses: None,
+            uses: 0,
+            parent_id: None,
+            payer: None,
+            paid_at: 0,
+            metadata: None,
+            discount: 0,
+            coupon: None,
+            tip: 0,
+            tip_receiver: None,
+            auto_confirm_after: None,
+            tracking_ref: None,
+            shipped_at: 0,
+            delivered_at: 0,
+            history: vec![],
+            shop_storage: 0,
+            payer_storage: 0,
+        }
+    }
+}
+
+impl Status {
+    /// Statuses a stored payment can be in.
+    pub fn tracked() -> Vec<Status> {
+        vec![
+            Status::REQUESTING,
+            Status::PAID,
+            Status::SHIPPED,
+            Status::DELIVERED,
+            Status::CONFIRMED,
+            Status::CLAIMED,
+            Status::REFUNDED,
+            Status::DISPUTED,
+        ]
+    }
+
+    /// Whether the contract holds what the payer paid while a payment is in this status.
+    pub fn holds_funds(&self) -> bool {
+        matches!(self, Status::PAID | Status::SHIPPED | Status::DELIVERED | Status::DISPUTED | Status::CONFIRMED)
+    }
+}
+
+impl Payment {
+    /// What the payer owes: the fee less any coupon discount, plus the tip.
+    pub fn amount(&self) -> Balance {
+        self.fee - self.discount + self.tip
+    }
+
+    /// Bytes its status history takes in the stored record.
+    pub fn history_storage(&self) -> StorageUsage {
+        self.history
+            .iter()
+            .map(|change| change.try_to_vec().unwrap().len() as StorageUsage)
+            .sum()
+    }
+
+    /// Paid and waiting for the user to confirm, possibly already shipped or delivered.
+    pub fn is_awaiting_confirmation(&self) -> bool {
+        matches!(self.status, Status::PAID | Status::SHIPPED | Status::DELIVERED)
+    }
+
+    /// Confirmed, or awaiting confirmation and left undisputed for longer than its
+    /// auto-confirm window, counted from delivery when the shop marked it delivered.
+    pub fn is_claimable(&self, now: u64) -> bool {
+        if self.status == Status::CONFIRMED {
+            return true;
+        }
+        let window_start = if self.delivered_at > 0 { self.delivered_at } else { self.paid_at };
+        self.is_awaiting_confirmation()
+            && self.auto_confirm_after.map_or(false, |auto_confirm_after| now >= window_start + auto_confirm_after)
+    }
+
+    /// Placeholder for a pay_id that was issued and has since been archived.
+    pub fn archived(payment_id: u128) -> Self {
+        Payment {
+            payment_id,
+            status: Status::ARCHIVED,
+            ..Payment::default()
+        }
+    }
+}
+
+impl From<PaymentV1> for Payment {
+    /// Fields added since were unknown then: nothing was recorded about payers, coupons,
+    /// tips or shipping, and the shop paid for storage with a plain deposit.
+    fn from(payment: PaymentV1) -> Self {
+        Payment {
+            payment_id: payment.payment_id,
+            order_id: payment.order_id,
+            shop: payment.shop,
+            user: Some(payment.user),
+            msg: payment.msg,
+            fee: payment.fee,
+            status: payment.status,
+            ..Payment::default()
         }
     }
 }
@@ -43,6 +181,7 @@ impl Default for Payment {
 impl From<UpgradePayment> for Payment {
     fn from(upgradable_payment: UpgradePayment) -> Self {
         match upgradable_payment {
+            UpgradePayment::V1(payment) => Payment::from(payment),
             UpgradePayment::Current(payment) => payment,
         }
     }
@@ -54,16 +193,41 @@ impl From<Payment> for UpgradePayment {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
 #[serde(crate = "near_sdk::serde")]
 pub struct PaymentJson {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
     pub payment_id: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
     pub order_id: U128,
     pub shop: AccountId,
-    pub user: AccountId,
+    pub user: Option<AccountId>,
     pub msg: String,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
     pub fee: U128,
     pub status: Status,
+    pub max_uses: Option<u64>,
+    pub uses: u64,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub parent_id: Option<U128>,
+    pub payer: Option<AccountId>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub paid_at: U64,
+    pub metadata: Option<PaymentMetadata>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub discount: U128,
+    pub coupon: Option<String>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub tip: U128,
+    pub tip_receiver: Option<AccountId>,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub auto_confirm_after: Option<U64>,
+    pub tracking_ref: Option<String>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub shipped_at: U64,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub delivered_at: U64,
 }
 
 impl PaymentJson {
@@ -76,6 +240,37 @@ impl PaymentJson {
             msg: payment.msg,
             fee: U128(payment.fee),
             status: payment.status,
+            max_uses: payment.max_uses,
+            uses: payment.uses,
+            parent_id: payment.parent_id.map(U128),
+            payer: payment.payer,
+            paid_at: U64(payment.paid_at),
+            metadata: payment.metadata,
+            discount: U128(payment.discount),
+            coupon: payment.coupon,
+            tip: U128(payment.tip),
+            tip_receiver: payment.tip_receiver,
+            auto_confirm_after: payment.auto_confirm_after.map(U64),
+            tracking_ref: payment.tracking_ref,
+            shipped_at: U64(payment.shipped_at),
+            delivered_at: U64(payment.delivered_at),
         }
     }
 }
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentRequest {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub order_id: U128,
+    pub user_id: Option<AccountId>,
+    pub msg: String,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub fee: U128,
+    pub max_uses: Option<u64>,
+    pub metadata: Option<PaymentMetadata>,
+    pub tip_receiver: Option<AccountId>,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub auto_confirm_after: Option<U64>,
+}
@@ -0,0 +1,317 @@
+use crate::*;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::test_utils::{accounts, get_logs, testing_env_with_promise_results, VMContextBuilder};
+use near_sdk::serde_json;
+use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+use proptest::prelude::*;
+
+const STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000; // 0.1 NEAR
+const CONTRACT_BALANCE: Balance = 100_000_000_000_000_000_000_000_000_000_000_000; // 10^11 NEAR
+/// Roughly the total NEAR supply, so no payable fee is larger.
+const MAX_FEE: Balance = 1_000_000_000_000_000_000_000_000_000_000_000;
+
+fn owner() -> ValidAccountId {
+    accounts(0)
+}
+
+fn shops() -> [ValidAccountId; 2] {
+    [accounts(1), accounts(2)]
+}
+
+fn users() -> [ValidAccountId; 2] {
+    [accounts(3), accounts(4)]
+}
+
+fn context(predecessor: ValidAccountId, deposit: Balance) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(accounts(5))
+        .signer_account_id(predecessor.clone())
+        .predecessor_account_id(predecessor)
+        .account_balance(CONTRACT_BALANCE)
+        .attached_deposit(deposit);
+    builder
+}
+
+/// The amount logged after `key` in the last log line, as 42 for "amount" in "Withdraw: amount 42".
+fn logged(key: &str) -> Option<Balance> {
+    let log = get_logs().last().unwrap().clone();
+    let words: Vec<&str> = log.split(|c| c == ' ' || c == ',').filter(|word| !word.is_empty()).collect();
+    words.windows(2).find(|pair| pair[0] == key).map(|pair| pair[1].parse().unwrap())
+}
+
+/// Yocto sent out by the transfers the last call created, refunds of unspent deposit included.
+fn transferred() -> Balance {
+    let mut blockchain = env::take_blockchain_interface().unwrap();
+    let receipts = serde_json::to_value(blockchain.as_mut_mocked_blockchain().unwrap().created_receipts()).unwrap();
+    env::set_blockchain_interface(blockchain);
+
+    receipts
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|receipt| receipt["actions"].as_array().unwrap())
+        .filter_map(|action| action.get("Transfer"))
+        .map(|transfer| transfer["deposit"].as_str().unwrap().parse::<Balance>().unwrap())
+        .sum()
+}
+
+/// Whether `fee` is `percent` of `amount` in units of 1/DECIMALS, rounded down: the largest
+/// fee whose share of `amount` does not exceed `percent`.
+fn is_platform_fee(fee: Balance, amount: Balance, percent: u128) -> bool {
+    let decimals = DECIMALS as u128;
+    // both sides fit in a u128 since `amount` is at most MAX_FEE
+    fee * decimals <= amount * percent && amount * percent < (fee + 1) * decimals
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Request { shop: usize, user: usize, fee: Balance },
+    /// Operations on a payment pick the n-th one, modulo the payments in the status they need.
+    Pay(usize),
+    Confirm(usize),
+    Claim(usize),
+    Withdraw,
+    SetPaymentFee(u128),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..2usize, 0..2usize, prop_oneof![1..1_000u128, 1..=MAX_FEE])
+            .prop_map(|(shop, user, fee)| Op::Request { shop, user, fee }),
+        any::<usize>().prop_map(Op::Pay),
+        any::<usize>().prop_map(Op::Confirm),
+        any::<usize>().prop_map(Op::Claim),
+        Just(Op::Withdraw),
+        (1..=DECIMALS as u128).prop_map(Op::SetPaymentFee),
+    ]
+}
+
+struct ModelPayment {
+    shop: ValidAccountId,
+    user: ValidAccountId,
+    fee: Balance,
+    status: Status,
+}
+
+/// What the contract should hold, tracked independently of it.
+#[derive(Default)]
+struct Model {
+    payment_fee_percent: u128,
+    payments: Vec<ModelPayment>,
+    /// Everything attached to calls.
+    deposited: Balance,
+    /// Everything the contract sent out in transfers.
+    transferred: Balance,
+    /// Platform fees taken by claims.
+    fees: Balance,
+    withdrawn: Balance,
+    /// Kept to pay for requests and for the history of claimed payments.
+    storage: Balance,
+    /// Attached to calls that need a full access key and kept by the contract.
+    one_yocto: Balance,
+}
+
+impl Model {
+    /// pay_id of the n-th payment in `status`, if there is any.
+    fn pick(&self, n: usize, status: Status) -> Option<u128> {
+        let pay_ids: Vec<u128> = self.payments
+            .iter()
+            .enumerate()
+            .filter(|(_, payment)| payment.status == status)
+            .map(|(index, _)| index as u128 + 1)
+            .collect();
+        if pay_ids.is_empty() {
+            return None;
+        }
+        Some(pay_ids[n % pay_ids.len()])
+    }
+
+    fn payment(&mut self, pay_id: u128) -> &mut ModelPayment {
+        &mut self.payments[pay_id as usize - 1]
+    }
+
+    fn escrow(&self) -> Balance {
+        self.payments
+            .iter()
+            .filter(|payment| payment.status == Status::PAID || payment.status == Status::CONFIRMED)
+            .map(|payment| payment.fee)
+            .sum()
+    }
+
+    fn check(&self, contract: &PaymentShop) -> Result<(), TestCaseError> {
+        for (index, payment) in self.payments.iter().enumerate() {
+            let payment_json = contract.get_payment_info(U128(index as u128 + 1)).unwrap();
+            prop_assert_eq!(&payment_json.status, &payment.status);
+        }
+
+        // every yocto attached and not sent back out is held for a payment, kept as a fee
+        // that is not withdrawn yet or pays for storage
+        prop_assert!(self.transferred <= self.deposited);
+        prop_assert_eq!(self.deposited - self.transferred, self.escrow() + self.fees - self.withdrawn + self.storage + self.one_yocto);
+        prop_assert_eq!(contract.total_payment, self.fees);
+        prop_assert_eq!(contract.total_payment_withdraw, self.withdrawn);
+        prop_assert!(self.withdrawn <= self.fees);
+
+        let breakdown = contract.get_balance_breakdown();
+        prop_assert_eq!(breakdown.escrow.0, self.escrow());
+        prop_assert_eq!(breakdown.pending_fees.0, self.fees - self.withdrawn);
+
+        let platform_stats = contract.get_platform_stats();
+        prop_assert_eq!(platform_stats.total_fees.0, self.fees);
+        for counter in platform_stats.by_status.iter() {
+            let count = self.payments.iter().filter(|payment| payment.status == counter.status).count();
+            prop_assert_eq!(counter.count, count as u64, "{:?}", counter.status);
+        }
+
+        Ok(())
+    }
+}
+
+fn run(payment_fee_percent: u128, ops: Vec<Op>) -> Result<(), TestCaseError> {
+    // start every case from empty storage
+    env::take_blockchain_interface();
+    testing_env!(context(owner(), 0).build());
+    let mut contract = PaymentShop::new(owner().into(), U128(payment_fee_percent));
+    let mut model = Model { payment_fee_percent, ..Default::default() };
+
+    for op in ops {
+        match op {
+            Op::Request { shop, user, fee } => {
+                let order_id = model.payments.len() as u128 + 1;
+                testing_env!(context(shops()[shop].clone(), STORAGE_DEPOSIT).build());
+                contract.req_payment(U128(order_id), Some(users()[user].clone().into()), String::new(), U128(fee), None, None, None, None);
+
+                let refund = transferred();
+                prop_assert!(refund <= STORAGE_DEPOSIT);
+                model.deposited += STORAGE_DEPOSIT;
+                model.transferred += refund;
+                model.storage += STORAGE_DEPOSIT - refund;
+                model.payments.push(ModelPayment {
+                    shop: shops()[shop].clone(),
+                    user: users()[user].clone(),
+                    fee,
+                    status: Status::REQUESTING,
+                });
+            }
+            Op::Pay(n) => {
+                if let Some(pay_id) = model.pick(n, Status::REQUESTING) {
+                    let payment = model.payment(pay_id);
+                    testing_env!(context(payment.user.clone(), payment.fee).build());
+                    contract.pay(U128(pay_id), None);
+
+                    // nothing of an exact fee is left to refund
+                    prop_assert_eq!(transferred(), 0);
+                    let fee = payment.fee;
+                    payment.status = Status::PAID;
+                    model.deposited += fee;
+                }
+            }
+            Op::Confirm(n) => {
+                if let Some(pay_id) = model.pick(n, Status::PAID) {
+                    let payment = model.payment(pay_id);
+                    testing_env!(context(payment.user.clone(), 1).build());
+                    contract.confirm(U128(pay_id));
+
+                    prop_assert_eq!(transferred(), 0);
+                    payment.status = Status::CONFIRMED;
+                    model.deposited += 1;
+                    model.one_yocto += 1;
+                }
+            }
+            Op::Claim(n) => {
+                if let Some(pay_id) = model.pick(n, Status::CONFIRMED) {
+                    let payment_fee_percent = model.payment_fee_percent;
+                    let payment = model.payment(pay_id);
+                    testing_env!(context(payment.shop.clone(), 1).build());
+                    contract.claim(U128(pay_id));
+
+                    // the history is paid out of the shop's amount, as far as it covers it,
+                    // and archiving gives it back
+                    let fee = payment.fee * payment_fee_percent / DECIMALS as u128;
+                    let byte_cost = env::storage_byte_cost();
+                    let history_storage = Balance::from(contract.internal_get_payment(pay_id).history_storage());
+                    let history_cost = byte_cost * std::cmp::min(history_storage, (payment.fee - fee) / byte_cost);
+                    let amount = transferred();
+                    prop_assert_eq!(amount, payment.fee - fee - history_cost, "fee {} of {} at {}", fee, payment.fee, payment_fee_percent);
+                    prop_assert_eq!(logged("amount"), Some(amount));
+                    payment.status = Status::CLAIMED;
+                    model.deposited += 1;
+                    model.transferred += amount;
+                    model.fees += fee;
+                    model.storage += history_cost;
+                    model.one_yocto += 1;
+                }
+            }
+            Op::Withdraw => {
+                if model.fees > model.withdrawn {
+                    testing_env!(context(owner(), 1).build());
+                    contract.withdraw();
+                    let amount = transferred();
+
+                    // the transfer's callback logs the withdrawal
+                    testing_env_with_promise_results(context(accounts(5), 0).build(), PromiseResult::Successful(vec![]));
+                    contract.resolve_withdrawal(U128(amount), owner().into(), None);
+                    prop_assert_eq!(logged("amount"), Some(amount));
+                    prop_assert_eq!(amount, model.fees - model.withdrawn);
+                    model.deposited += 1;
+                    model.transferred += amount;
+                    model.withdrawn += amount;
+                    model.one_yocto += 1;
+                }
+            }
+            Op::SetPaymentFee(payment_fee_percent) => {
+                testing_env!(context(owner(), 1).build());
+                contract.set_payment_fee(U128(payment_fee_percent));
+
+                model.payment_fee_percent = payment_fee_percent;
+                model.deposited += 1;
+                model.one_yocto += 1;
+            }
+        }
+
+        model.check(&contract)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_platform_fee_examples() {
+    // (fee, percent, shop amount) worked out by hand
+    let examples = [
+        (10_000_000_000_000_000_000_000_000, 20000, 8_000_000_000_000_000_000_000_000),
+        (999, 33333, 667),
+        (1, 99999, 1),
+        (7, 100000, 0),
+        (MAX_FEE, 1, 999_990_000_000_000_000_000_000_000_000_000),
+    ];
+    for (fee, payment_fee_percent, shop_amount) in examples.iter().copied() {
+        env::take_blockchain_interface();
+        testing_env!(context(owner(), 0).build());
+        let mut contract = PaymentShop::new(owner().into(), U128(payment_fee_percent));
+        let (shop, user) = (shops()[0].clone(), users()[0].clone());
+
+        testing_env!(context(shop.clone(), STORAGE_DEPOSIT).build());
+        contract.req_payment(U128(1), Some(user.clone().into()), String::new(), U128(fee), None, None, None, None);
+        testing_env!(context(user.clone(), fee).build());
+        contract.pay(U128(1), None);
+        testing_env!(context(user, 1).build());
+        contract.confirm(U128(1));
+        testing_env!(context(shop, 1).build());
+        contract.claim(U128(1));
+
+        let amount = logged("amount").unwrap() + logged("storage").unwrap_or(0);
+        assert_eq!(amount, shop_amount, "fee {} at {}", fee, payment_fee_percent);
+        assert!(is_platform_fee(fee - shop_amount, fee, payment_fee_percent));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_balance_conservation(payment_fee_percent in 0..=DECIMALS as u128, ops in prop::collection::vec(op(), 1..48)) {
+        run(payment_fee_percent, ops)?;
+    }
+}
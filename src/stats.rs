@@ -0,0 +1,154 @@
+use crate::*;
+use near_sdk::json_types::U64;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct StatusCounter {
+    pub status: Status,
+    pub count: u64,
+    pub volume: Balance,
+    /// What the contract holds for payments in the status: fee less discount plus tip.
+    pub escrow: Balance,
+}
+
+/// Running counters kept per shop and for the whole platform. Counts and volumes
+/// are by current status, so a payment moves from one counter to the next.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct PaymentStats {
+    pub by_status: Vec<StatusCounter>,
+    pub total_fees: Balance,
+    pub total_refunded: Balance,
+    pub confirm_time_total: u64,
+    pub confirm_count: u64,
+}
+
+impl Default for PaymentStats {
+    fn default() -> Self {
+        PaymentStats {
+            // every status is allocated upfront so later transitions never grow storage
+            by_status: Status::tracked()
+                .into_iter()
+                .map(|status| StatusCounter { status, count: 0, volume: 0, escrow: 0 })
+                .collect(),
+            total_fees: 0,
+            total_refunded: 0,
+            confirm_time_total: 0,
+            confirm_count: 0,
+        }
+    }
+}
+
+impl PaymentStats {
+    fn counter(&mut self, status: &Status) -> &mut StatusCounter {
+        if let Some(index) = self.by_status.iter().position(|counter| &counter.status == status) {
+            return &mut self.by_status[index];
+        }
+        self.by_status.push(StatusCounter { status: status.clone(), count: 0, volume: 0, escrow: 0 });
+        self.by_status.last_mut().unwrap()
+    }
+
+    pub fn record_status(&mut self, from: Option<&Status>, to: &Status, volume: Balance) {
+        if let Some(from) = from {
+            let counter = self.counter(from);
+            counter.count -= 1;
+            counter.volume -= volume;
+        }
+        let counter = self.counter(to);
+        counter.count += 1;
+        counter.volume += volume;
+    }
+
+    /// Moves `amount` held by the contract along with a payment changing status.
+    pub fn record_escrow(&mut self, from: &Status, to: &Status, amount: Balance) {
+        if from.holds_funds() {
+            self.counter(from).escrow -= amount;
+        }
+        if to.holds_funds() {
+            self.counter(to).escrow += amount;
+        }
+    }
+
+    /// What the contract holds for all payments together.
+    pub fn escrow(&self) -> Balance {
+        self.by_status.iter().map(|counter| counter.escrow).sum()
+    }
+
+    pub fn record_confirm_time(&mut self, duration: u64) {
+        self.confirm_time_total += duration;
+        self.confirm_count += 1;
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatusCounterJson {
+    pub status: Status,
+    pub count: u64,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub volume: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub escrow: U128,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentStatsJson {
+    pub by_status: Vec<StatusCounterJson>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub total_fees: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub total_refunded: U128,
+    /// Average nanoseconds from PAID to CONFIRMED.
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub average_confirm_time: U64,
+}
+
+impl From<PaymentStats> for PaymentStatsJson {
+    fn from(stats: PaymentStats) -> Self {
+        let average_confirm_time = if stats.confirm_count > 0 {
+            stats.confirm_time_total / stats.confirm_count
+        } else {
+            0
+        };
+
+        PaymentStatsJson {
+            by_status: stats.by_status
+                .into_iter()
+                .map(|counter| StatusCounterJson {
+                    status: counter.status,
+                    count: counter.count,
+                    volume: U128(counter.volume),
+                    escrow: U128(counter.escrow)
+                })
+                .collect(),
+            total_fees: U128(stats.total_fees),
+            total_refunded: U128(stats.total_refunded),
+            average_confirm_time: U64(average_confirm_time),
+        }
+    }
+}
+
+impl PaymentShop {
+    /// Applies `update` to the stats of `shop_id` and to the platform stats.
+    pub(crate) fn internal_update_stats<F: Fn(&mut PaymentStats)>(&mut self, shop_id: &AccountId, update: F) {
+        let mut shop_stats = self.shop_stats.get(shop_id).unwrap_or_default();
+        update(&mut shop_stats);
+        self.shop_stats.insert(shop_id, &shop_stats);
+
+        update(&mut self.platform_stats);
+    }
+
+    /// Moves `payment` to `status`, keeping the status counters in step.
+    pub(crate) fn internal_set_status(&mut self, payment: &mut Payment, status: Status) {
+        let from = payment.status.clone();
+        let volume = payment.fee;
+        let escrow = payment.amount();
+        self.internal_update_stats(&payment.shop, |stats| {
+            stats.record_status(Some(&from), &status, volume);
+            stats.record_escrow(&from, &status, escrow);
+        });
+
+        payment.status = status;
+    }
+}
@@ -0,0 +1,170 @@
+use crate::*;
+use crate::errors::*;
+use near_sdk::json_types::ValidAccountId;
+
+/// Bytes taken by a registered account's `StorageAccount` record.
+const STORAGE_ACCOUNT_BYTES: u64 = 150;
+
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct StorageAccount {
+    pub total: Balance,
+    pub used: Balance,
+}
+
+impl StorageAccount {
+    pub fn available(&self) -> Balance {
+        self.total - self.used
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub total: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub available: U128,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub min: U128,
+    #[cfg_attr(feature = "abi", schemars(with = "Option<crate::abi::Decimal>"))]
+    pub max: Option<U128>,
+}
+
+#[near_bindgen]
+impl PaymentShop {
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id: AccountId = account_id.map(|account_id| account_id.into()).unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+
+        match self.storage_accounts.get(&account_id) {
+            Some(mut storage_account) => {
+                if registration_only {
+                    if amount > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(amount);
+                    }
+                } else {
+                    storage_account.total += amount;
+                    self.internal_set_storage_account(&account_id, &storage_account);
+                }
+            },
+            None => {
+                let min_balance = self.storage_balance_bounds().min.0;
+                require(amount >= min_balance, ContractError::StorageDepositTooLow);
+
+                let total = if registration_only { min_balance } else { amount };
+                self.internal_set_storage_account(&account_id, &StorageAccount { total, used: min_balance });
+
+                if amount > total {
+                    Promise::new(env::predecessor_account_id()).transfer(amount - total);
+                }
+            }
+        }
+
+        let log_message = format!("Storage deposit: account: {}, amount {}", account_id, amount);
+        env::log(log_message.as_bytes());
+
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let storage_account = self.storage_accounts.get(&account_id);
+        require(storage_account.is_some(), ContractError::NotRegistered);
+        let mut storage_account = storage_account.unwrap();
+
+        let available = storage_account.available();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        require(amount <= available, ContractError::StorageWithdrawTooHigh);
+
+        storage_account.total -= amount;
+        self.internal_set_storage_account(&account_id, &storage_account);
+
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+
+        let log_message = format!("Storage withdraw: account: {}, amount {}", account_id, amount);
+        env::log(log_message.as_bytes());
+
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(env::storage_byte_cost() * Balance::from(STORAGE_ACCOUNT_BYTES)),
+            max: None
+        }
+    }
+}
+
+impl PaymentShop {
+    /// Stores the storage balance of `account_id`, keeping `storage_available` in step.
+    fn internal_set_storage_account(&mut self, account_id: &AccountId, storage_account: &StorageAccount) {
+        let previous_available = self.storage_accounts.get(account_id).map_or(0, |previous| previous.available());
+        self.storage_available = self.storage_available - previous_available + storage_account.available();
+        self.storage_accounts.insert(account_id, storage_account);
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(account_id).map(|storage_account| StorageBalance {
+            total: U128(storage_account.total),
+            available: U128(storage_account.available())
+        })
+    }
+
+    /// Charges `storage_used` bytes to the storage balance of `account_id`, topped up
+    /// with the attached deposit. Accounts without a storage balance pay from the
+    /// attached deposit and get the rest refunded.
+    pub(crate) fn internal_charge_storage(&mut self, account_id: &AccountId, storage_used: u64) {
+        match self.storage_accounts.get(account_id) {
+            Some(mut storage_account) => {
+                storage_account.total += env::attached_deposit();
+
+                let required_cost = env::storage_byte_cost() * Balance::from(storage_used);
+                require(storage_account.available() >= required_cost, ContractError::InsufficientStorageBalance { required: required_cost });
+
+                storage_account.used += required_cost;
+                self.internal_set_storage_account(account_id, &storage_account);
+            },
+            None => refund_deposit(storage_used)
+        }
+    }
+
+    /// Gives the cost of `storage_freed` bytes back to `account_id`, into its storage
+    /// balance when it has one, as a transfer otherwise.
+    pub(crate) fn internal_release_storage(&mut self, account_id: &AccountId, storage_freed: u64) {
+        let amount = env::storage_byte_cost() * Balance::from(storage_freed);
+
+        match self.storage_accounts.get(account_id) {
+            Some(mut storage_account) => {
+                let min_balance = self.storage_balance_bounds().min.0;
+                let released = std::cmp::min(amount, storage_account.used - min_balance);
+
+                storage_account.used -= released;
+                storage_account.total += amount - released;
+                self.internal_set_storage_account(account_id, &storage_account);
+            },
+            None => {
+                if amount > 0 {
+                    Promise::new(account_id.clone()).transfer(amount);
+                }
+            }
+        }
+    }
+}
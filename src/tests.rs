@@ -0,0 +1,1483 @@
+use crate::*;
+use crate::upgrade::PaymentShopV1;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::test_utils::{accounts, get_logs, testing_env_with_promise_results, VMContextBuilder};
+use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+const PAYMENT_FEE_PERCENT: u128 = 20000; // 20%
+const FEE: Balance = 10_000_000_000_000_000_000_000_000; // 10 NEAR
+const STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000; // 0.1 NEAR
+const CONTRACT_BALANCE: Balance = 1_000_000_000_000_000_000_000_000_000; // 1000 NEAR
+
+fn owner() -> ValidAccountId {
+    accounts(0)
+}
+
+fn shop() -> ValidAccountId {
+    accounts(1)
+}
+
+fn user() -> ValidAccountId {
+    accounts(2)
+}
+
+fn stranger() -> ValidAccountId {
+    accounts(3)
+}
+
+fn context(predecessor: ValidAccountId, deposit: Balance) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(accounts(5))
+        .signer_account_id(predecessor.clone())
+        .predecessor_account_id(predecessor)
+        .account_balance(CONTRACT_BALANCE)
+        .attached_deposit(deposit);
+    builder
+}
+
+fn setup() -> PaymentShop {
+    testing_env!(context(owner(), 0).build());
+    PaymentShop::new(owner().into(), U128(PAYMENT_FEE_PERCENT))
+}
+
+fn request(contract: &mut PaymentShop, order_id: u128) {
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(order_id), Some(user().into()), "Hello".to_string(), U128(FEE), None, None, None, None);
+}
+
+fn pay(contract: &mut PaymentShop, pay_id: u128) {
+    testing_env!(context(user(), FEE).build());
+    contract.pay(U128(pay_id), None);
+}
+
+fn confirm(contract: &mut PaymentShop, pay_id: u128) {
+    testing_env!(context(user(), 1).build());
+    contract.confirm(U128(pay_id));
+}
+
+fn claim(contract: &mut PaymentShop, pay_id: u128) {
+    testing_env!(context(shop(), 1).build());
+    contract.claim(U128(pay_id));
+}
+
+/// A payment of `FEE` requested, paid and confirmed as pay_id 1.
+fn setup_confirmed() -> PaymentShop {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+    confirm(&mut contract, 1);
+    contract
+}
+
+/// Runs the callback of a withdrawal's transfer, as the contract itself.
+fn resolve_withdrawal(contract: &mut PaymentShop, amount: Balance, receiver: ValidAccountId, proposal_id: Option<u64>, result: PromiseResult) -> bool {
+    testing_env_with_promise_results(context(accounts(5), 0).build(), result);
+    contract.resolve_withdrawal(U128(amount), receiver.into(), proposal_id.map(U64))
+}
+
+/// What storing the status history of `pay_id` costs, paid out of what it settles.
+fn history_cost(contract: &PaymentShop, pay_id: u128) -> Balance {
+    env::storage_byte_cost() * Balance::from(contract.internal_get_payment(pay_id).history_storage())
+}
+
+fn status(contract: &PaymentShop, pay_id: u128) -> Status {
+    contract.get_payment_info(U128(pay_id)).unwrap().status
+}
+
+#[test]
+fn test_new() {
+    let contract = setup();
+
+    let shop_info = contract.get_payment_shop_info();
+    assert_eq!(shop_info.pay_id.0, 0);
+    assert_eq!(shop_info.payment_fee_percent.0, PAYMENT_FEE_PERCENT);
+    assert_eq!(shop_info.total_payment.0, 0);
+    assert_eq!(shop_info.tip_fee_percent.0, 0);
+    assert_eq!(contract.owner_id, AccountId::from(owner()));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_PAYMENT_FEE")]
+fn test_new_payment_fee_above_decimals() {
+    testing_env!(context(owner(), 0).build());
+    PaymentShop::new(owner().into(), U128(DECIMALS as u128 + 1));
+}
+
+#[test]
+fn test_new_full_payment_fee() {
+    testing_env!(context(owner(), 0).build());
+    let contract = PaymentShop::new(owner().into(), U128(DECIMALS as u128));
+
+    assert_eq!(contract.get_payment_shop_info().payment_fee_percent.0, DECIMALS as u128);
+}
+
+#[test]
+fn test_req_payment() {
+    let mut contract = setup();
+    request(&mut contract, 7);
+
+    let payment = contract.get_payment_info(U128(1)).unwrap();
+    assert_eq!(payment.order_id.0, 7);
+    assert_eq!(payment.shop, AccountId::from(shop()));
+    assert_eq!(payment.user, Some(user().into()));
+    assert_eq!(payment.fee.0, FEE);
+    assert_eq!(payment.status, Status::REQUESTING);
+    assert_eq!(contract.get_payid_from_orderid(U128(7)), Some(U128(1)));
+    assert_eq!(get_logs()[0], format!("Request payment: payment_id: 1, order_id: 7, account: {}, fee: {}, data: Hello", AccountId::from(user()), FEE));
+}
+
+#[test]
+#[should_panic(expected = "ERR_DEPOSIT_AT_LEAST_ONE_YOCTO")]
+fn test_req_payment_without_deposit() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), 0).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), None, None, None, None);
+}
+
+fn line_items_metadata(line_items: Vec<(u32, Balance)>, tax: Balance) -> PaymentMetadata {
+    PaymentMetadata {
+        line_items: line_items
+            .into_iter()
+            .map(|(qty, unit_price)| LineItem { sku: "sku".to_string(), qty, unit_price: U128(unit_price) })
+            .collect(),
+        currency: None,
+        tax: Some(U128(tax)),
+        reference: None,
+        reference_hash: None,
+    }
+}
+
+#[test]
+fn test_metadata_line_items_add_up() {
+    line_items_metadata(vec![(2, FEE / 4), (1, FEE / 4)], FEE / 4).assert_valid(FEE);
+}
+
+#[test]
+#[should_panic(expected = "ERR_LINE_ITEMS_MISMATCH")]
+fn test_metadata_line_item_price_overflow() {
+    line_items_metadata(vec![(u32::MAX, Balance::MAX / 2)], 0).assert_valid(FEE);
+}
+
+#[test]
+#[should_panic(expected = "ERR_LINE_ITEMS_MISMATCH")]
+fn test_metadata_line_items_total_overflow() {
+    line_items_metadata(vec![(1, Balance::MAX), (1, 1)], 0).assert_valid(FEE);
+}
+
+#[test]
+#[should_panic(expected = "ERR_LINE_ITEMS_MISMATCH")]
+fn test_metadata_tax_overflow() {
+    line_items_metadata(vec![(1, Balance::MAX)], 1).assert_valid(FEE);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INSUFFICIENT_STORAGE_DEPOSIT")]
+fn test_req_payment_storage_not_covered() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), 1).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), None, None, None, None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ORDER_ID_EXISTS")]
+fn test_req_payment_duplicate_order_id() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    request(&mut contract, 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_MESSAGE_TOO_LONG")]
+fn test_req_payment_message_too_long() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), None, "x".repeat(MAX_MSG_LENGTH + 1), U128(FEE), None, None, None, None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_MAX_USES_NOT_ALLOWED")]
+fn test_req_payment_max_uses_with_user() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), Some(user().into()), "Hello".to_string(), U128(FEE), Some(2), None, None, None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_AUTO_CONFIRM")]
+fn test_req_payment_auto_confirm_too_long() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), None, None, None, Some(U64(MAX_AUTO_CONFIRM_AFTER + 1)));
+}
+
+#[test]
+fn test_req_payments() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    let pay_ids = contract.req_payments((1..=3).map(|order_id| PaymentRequest {
+        order_id: U128(order_id),
+        user_id: Some(user().into()),
+        msg: "Hello".to_string(),
+        fee: U128(FEE),
+        max_uses: None,
+        metadata: None,
+        tip_receiver: None,
+        auto_confirm_after: None,
+    }).collect());
+
+    assert_eq!(pay_ids, vec![U128(1), U128(2), U128(3)]);
+    assert_eq!(contract.get_payid_from_orderid(U128(3)), Some(U128(3)));
+}
+
+#[test]
+#[should_panic(expected = "ERR_NO_PAYMENTS")]
+fn test_req_payments_empty() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payments(vec![]);
+}
+
+#[test]
+fn test_pay() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    let payment = contract.get_payment_info(U128(1)).unwrap();
+    assert_eq!(payment.status, Status::PAID);
+    assert_eq!(payment.payer, None);
+    assert_eq!(get_logs(), vec!["Pay: payment_id: 1".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INSUFFICIENT_FEE")]
+fn test_pay_insufficient_fee() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(user(), FEE - 1).build());
+    contract.pay(U128(1), None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_pay_wrong_user() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(stranger(), FEE).build());
+    contract.pay(U128(1), None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_pay_twice() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+    pay(&mut contract, 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_PAYMENT_NOT_FOUND")]
+fn test_pay_unknown_payment() {
+    let mut contract = setup();
+    pay(&mut contract, 1);
+}
+
+#[test]
+fn test_pay_open_payment() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), None, None, None, None);
+
+    testing_env!(context(stranger(), FEE + STORAGE_DEPOSIT).build());
+    contract.pay(U128(1), None);
+
+    assert_eq!(contract.get_payment_info(U128(1)).unwrap().user, Some(stranger().into()));
+}
+
+#[test]
+fn test_pay_with_tip() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    let tip = FEE / 10;
+    testing_env!(context(user(), FEE + tip).build());
+    contract.pay(U128(1), Some(U128(tip)));
+
+    assert_eq!(contract.get_payment_info(U128(1)).unwrap().tip.0, tip);
+    confirm(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_tip_fee(U128(10000)); // 10%
+    claim(&mut contract, 1);
+
+    // 20% of the fee and 10% of the tip stay with the platform
+    let history_cost = history_cost(&contract, 1);
+    let shop_amount = FEE - FEE / 5 + tip - tip / 10 - history_cost;
+    assert_eq!(get_logs(), vec![format!("Shop claim: payment_id: 1, amount {}, storage {}", shop_amount, history_cost)]);
+    assert_eq!(contract.get_payment_shop_info().total_payment.0, FEE / 5 + tip / 10);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_TIP")]
+fn test_pay_with_overflowing_tip() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(user(), FEE).build());
+    contract.pay(U128(1), Some(U128(u128::MAX)));
+}
+
+#[test]
+fn test_pay_for() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(stranger(), FEE + STORAGE_DEPOSIT).build());
+    contract.pay_for(U128(1), None);
+
+    let payment = contract.get_payment_info(U128(1)).unwrap();
+    assert_eq!(payment.status, Status::PAID);
+    assert_eq!(payment.user, Some(user().into()));
+    assert_eq!(payment.payer, Some(stranger().into()));
+}
+
+#[test]
+#[should_panic(expected = "ERR_OPEN_PAYMENT_ON_BEHALF")]
+fn test_pay_for_open_payment() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), None, None, None, None);
+
+    testing_env!(context(stranger(), FEE + STORAGE_DEPOSIT).build());
+    contract.pay_for(U128(1), None);
+}
+
+#[test]
+fn test_pay_many() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    request(&mut contract, 2);
+
+    let extra = FEE / 2;
+    testing_env!(context(user(), 2 * FEE + extra).build());
+    contract.pay_many(vec![U128(1), U128(2)]);
+
+    assert_eq!(status(&contract, 1), Status::PAID);
+    assert_eq!(status(&contract, 2), Status::PAID);
+    assert_eq!(get_logs().last().unwrap(), &format!("Pay many: count: 2, amount {}, refund {}", 2 * FEE, extra));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INSUFFICIENT_FEE")]
+fn test_pay_many_insufficient_fee() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    request(&mut contract, 2);
+
+    testing_env!(context(user(), 2 * FEE - 1).build());
+    contract.pay_many(vec![U128(1), U128(2)]);
+}
+
+#[test]
+fn test_confirm() {
+    let contract = setup_confirmed();
+
+    assert_eq!(status(&contract, 1), Status::CONFIRMED);
+    assert_eq!(get_logs(), vec!["Confirm: payment_id: 1".to_string()]);
+}
+
+#[test]
+fn test_confirm_by_owner() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.confirm(U128(1));
+
+    assert_eq!(status(&contract, 1), Status::CONFIRMED);
+}
+
+#[test]
+#[should_panic(expected = "ERR_DEPOSIT_EXACTLY_ONE_YOCTO")]
+fn test_confirm_wrong_deposit() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(user(), 2).build());
+    contract.confirm(U128(1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_confirm_by_shop() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.confirm(U128(1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_confirm_unpaid() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    confirm(&mut contract, 1);
+}
+
+#[test]
+fn test_mark_shipped_and_delivered() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).block_timestamp(100).build());
+    contract.mark_shipped(U128(1), "DHL-123456".to_string());
+    assert_eq!(get_logs().last().unwrap(), "Ship: payment_id: 1");
+
+    testing_env!(context(shop(), 1).block_timestamp(200).build());
+    contract.mark_delivered(U128(1));
+
+    let payment = contract.get_payment_info(U128(1)).unwrap();
+    assert_eq!(payment.status, Status::DELIVERED);
+    assert_eq!(payment.tracking_ref, Some("DHL-123456".to_string()));
+    assert_eq!(payment.shipped_at.0, 100);
+    assert_eq!(payment.delivered_at.0, 200);
+
+    confirm(&mut contract, 1);
+    assert_eq!(status(&contract, 1), Status::CONFIRMED);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_mark_shipped_by_user() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(user(), STORAGE_DEPOSIT).build());
+    contract.mark_shipped(U128(1), "DHL-123456".to_string());
+}
+
+#[test]
+#[should_panic(expected = "ERR_TRACKING_REF_TOO_LONG")]
+fn test_mark_shipped_tracking_ref_too_long() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.mark_shipped(U128(1), "x".repeat(MAX_TRACKING_REF_LENGTH + 1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_mark_delivered_not_shipped() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.mark_delivered(U128(1));
+}
+
+#[test]
+fn test_dispute() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(user(), 1).build());
+    contract.dispute(U128(1));
+    assert_eq!(status(&contract, 1), Status::DISPUTED);
+
+    testing_env!(context(owner(), 1).build());
+    contract.confirm(U128(1));
+    assert_eq!(status(&contract, 1), Status::CONFIRMED);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_dispute_by_shop() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.dispute(U128(1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_confirm_disputed_by_user() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(user(), 1).build());
+    contract.dispute(U128(1));
+    confirm(&mut contract, 1);
+}
+
+#[test]
+fn test_claim() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    assert_eq!(status(&contract, 1), Status::CLAIMED);
+    // the shop pays for the payment's history out of its amount
+    let history_cost = history_cost(&contract, 1);
+    assert!(history_cost > 0);
+    assert_eq!(get_logs(), vec![format!("Shop claim: payment_id: 1, amount {}, storage {}", FEE - FEE / 5 - history_cost, history_cost)]);
+    assert_eq!(contract.get_payment_shop_info().total_payment.0, FEE / 5);
+}
+
+#[test]
+fn test_claim_rounds_fee_down() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), Some(user().into()), "Hello".to_string(), U128(4), None, None, None, None);
+
+    testing_env!(context(user(), 4).build());
+    contract.pay(U128(1), None);
+    confirm(&mut contract, 1);
+    claim(&mut contract, 1);
+
+    // 20% of 4 yocto rounds down to nothing, and 4 yocto pay for none of the history
+    assert_eq!(get_logs(), vec!["Shop claim: payment_id: 1, amount 4".to_string()]);
+    assert_eq!(contract.get_payment_shop_info().total_payment.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_claim_unconfirmed() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+    claim(&mut contract, 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_claim_by_stranger() {
+    let mut contract = setup_confirmed();
+
+    testing_env!(context(stranger(), 1).build());
+    contract.claim(U128(1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_claim_twice() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+    claim(&mut contract, 1);
+}
+
+#[test]
+fn test_claim_auto_confirm() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), Some(user().into()), "Hello".to_string(), U128(FEE), None, None, None, Some(U64(1000)));
+
+    testing_env!(context(user(), FEE).block_timestamp(1000).build());
+    contract.pay(U128(1), None);
+
+    testing_env!(context(shop(), 1).block_timestamp(2000).build());
+    contract.claim(U128(1));
+
+    assert_eq!(status(&contract, 1), Status::CLAIMED);
+    assert_eq!(get_logs()[0], "Auto confirm: payment_id: 1");
+    assert_eq!(contract.get_shop_stats(shop().into()).average_confirm_time.0, 1000);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_claim_before_auto_confirm() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.req_payment(U128(1), Some(user().into()), "Hello".to_string(), U128(FEE), None, None, None, Some(U64(1000)));
+
+    testing_env!(context(user(), FEE).block_timestamp(1000).build());
+    contract.pay(U128(1), None);
+
+    testing_env!(context(shop(), 1).block_timestamp(1999).build());
+    contract.claim(U128(1));
+}
+
+#[test]
+fn test_claim_many() {
+    let mut contract = setup();
+    for pay_id in 1..=2 {
+        request(&mut contract, pay_id);
+        pay(&mut contract, pay_id);
+        confirm(&mut contract, pay_id);
+    }
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_many(vec![U128(1), U128(2)]);
+
+    assert_eq!(status(&contract, 1), Status::CLAIMED);
+    assert_eq!(status(&contract, 2), Status::CLAIMED);
+    let history_cost = history_cost(&contract, 1) + history_cost(&contract, 2);
+    assert_eq!(get_logs().last().unwrap(), &format!("Shop claim many: count: 2, amount {}", 2 * (FEE - FEE / 5) - history_cost));
+}
+
+#[test]
+#[should_panic(expected = "ERR_NO_PAYMENTS")]
+fn test_claim_many_empty() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_many(vec![]);
+}
+
+#[test]
+fn test_claim_all() {
+    let mut contract = setup();
+    for pay_id in 1..=3 {
+        request(&mut contract, pay_id);
+        pay(&mut contract, pay_id);
+    }
+    confirm(&mut contract, 1);
+    confirm(&mut contract, 3);
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(1, None);
+    assert_eq!(get_logs().last().unwrap(), &format!("Shop claim many: count: 1, amount {}", FEE - FEE / 5 - history_cost(&contract, 1)));
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(10, None);
+
+    assert_eq!(status(&contract, 1), Status::CLAIMED);
+    assert_eq!(status(&contract, 2), Status::PAID);
+    assert_eq!(status(&contract, 3), Status::CLAIMED);
+}
+
+#[test]
+fn test_claim_all_from_index() {
+    let mut contract = setup();
+    for pay_id in 1..=3 {
+        request(&mut contract, pay_id);
+        pay(&mut contract, pay_id);
+    }
+    confirm(&mut contract, 3);
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(1, Some(U64(2)));
+
+    assert_eq!(status(&contract, 2), Status::PAID);
+    assert_eq!(status(&contract, 3), Status::CLAIMED);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NO_PAYMENTS")]
+fn test_claim_all_scans_only_limit() {
+    let mut contract = setup();
+    for pay_id in 1..=2 {
+        request(&mut contract, pay_id);
+        pay(&mut contract, pay_id);
+    }
+    confirm(&mut contract, 2);
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(1, None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NO_PAYMENTS")]
+fn test_claim_all_nothing_claimable() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(10, None);
+}
+
+#[test]
+fn test_refund() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.refund(U128(1));
+
+    assert_eq!(status(&contract, 1), Status::REFUNDED);
+    let history_cost = history_cost(&contract, 1);
+    assert_eq!(get_logs(), vec![format!("Refund: payment_id: 1, account: {}, amount {}, storage {}", AccountId::from(user()), FEE - history_cost, history_cost)]);
+    assert_eq!(contract.get_shop_stats(shop().into()).total_refunded.0, FEE);
+}
+
+#[test]
+fn test_refund_to_payer() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(stranger(), FEE + STORAGE_DEPOSIT).build());
+    contract.pay_for(U128(1), None);
+
+    testing_env!(context(owner(), 1).build());
+    contract.refund(U128(1));
+
+    let history_cost = history_cost(&contract, 1);
+    assert_eq!(get_logs(), vec![format!("Refund: payment_id: 1, account: {}, amount {}, storage {}", AccountId::from(stranger()), FEE - history_cost, history_cost)]);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_refund_by_user() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(user(), 1).build());
+    contract.refund(U128(1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_refund_claimed() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.refund(U128(1));
+}
+
+#[test]
+fn test_archive_payments() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.archive_payments(vec![U128(1)]);
+
+    assert_eq!(status(&contract, 1), Status::ARCHIVED);
+    assert_eq!(contract.get_payid_from_orderid(U128(1)), None);
+    assert!(contract.get_payment_history(U128(1)).is_none());
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_STATUS")]
+fn test_archive_open_payment() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.archive_payments(vec![U128(1)]);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_archive_by_stranger() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(stranger(), 1).build());
+    contract.archive_payments(vec![U128(1)]);
+}
+
+#[test]
+fn test_archive_releases_shop_storage() {
+    let mut contract = setup();
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.storage_deposit(None, None);
+    let available = |contract: &PaymentShop| contract.storage_balance_of(shop()).unwrap().available.0;
+
+    let before_request = available(&contract) + 1;
+    testing_env!(context(shop(), 1).build());
+    contract.req_payment(U128(1), Some(user().into()), "Hello".to_string(), U128(FEE), None, None, None, None);
+    let request_cost = before_request - available(&contract);
+    pay(&mut contract, 1);
+    confirm(&mut contract, 1);
+    claim(&mut contract, 1);
+
+    let before_archive = available(&contract);
+    let history_cost = history_cost(&contract, 1);
+    testing_env!(context(shop(), 1).build());
+    contract.archive_payments(vec![U128(1)]);
+
+    let released = available(&contract) - before_archive;
+    assert!(released > history_cost && released <= request_cost + history_cost);
+}
+
+#[test]
+fn test_archive_releases_payer_storage() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    testing_env!(context(user(), STORAGE_DEPOSIT).build());
+    contract.storage_deposit(None, None);
+    pay(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.refund(U128(1));
+    let history_cost = history_cost(&contract, 1);
+
+    let available = contract.storage_balance_of(user()).unwrap().available.0;
+    testing_env!(context(shop(), 1).build());
+    contract.archive_payments(vec![U128(1)]);
+
+    assert_eq!(contract.storage_balance_of(user()).unwrap().available.0, available + history_cost);
+}
+
+#[test]
+fn test_archive_link_payment_releases_only_history_to_shop() {
+    let mut contract = setup();
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.storage_deposit(None, None);
+    testing_env!(context(shop(), 1).build());
+    contract.req_payment(U128(1), None, "Hello".to_string(), U128(FEE), Some(5), None, None, None);
+
+    testing_env!(context(user(), FEE + STORAGE_DEPOSIT).build());
+    contract.pay(U128(1), None);
+    confirm(&mut contract, 2);
+    claim(&mut contract, 2);
+
+    let available = contract.storage_balance_of(shop()).unwrap().available.0;
+    let history_cost = history_cost(&contract, 2);
+    testing_env!(context(shop(), 1).build());
+    contract.archive_payments(vec![U128(2)]);
+
+    // the child record itself was paid by its payer
+    assert_eq!(status(&contract, 2), Status::ARCHIVED);
+    assert_eq!(contract.storage_balance_of(shop()).unwrap().available.0, available + history_cost);
+}
+
+#[test]
+fn test_create_coupon() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.create_coupon("SUMMER_2024-a".to_string(), Discount::Percent(U128(10000)), 5, None, None);
+
+    assert_eq!(contract.get_coupon(shop().into(), "SUMMER_2024-a".to_string()).unwrap().max_uses, 5);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_COUPON")]
+fn test_create_coupon_with_separator() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.create_coupon("SUMMER, tip: 1".to_string(), Discount::Percent(U128(10000)), 5, None, None);
+}
+
+#[test]
+fn test_withdraw() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+    assert!(get_logs().is_empty());
+    assert_eq!(contract.total_payment_withdraw, FEE / 5);
+
+    assert!(resolve_withdrawal(&mut contract, FEE / 5, owner(), None, PromiseResult::Successful(vec![])));
+    assert_eq!(get_logs(), vec![format!("Withdraw: amount {}", FEE / 5)]);
+    assert_eq!(contract.total_payment_withdraw, FEE / 5);
+}
+
+#[test]
+fn test_withdraw_failed_transfer() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+
+    assert!(!resolve_withdrawal(&mut contract, FEE / 5, owner(), None, PromiseResult::Failed));
+    let receiver: AccountId = owner().into();
+    assert_eq!(get_logs(), vec![format!("Withdraw failed: amount {}, receiver: {}", FEE / 5, receiver)]);
+    assert_eq!(contract.total_payment_withdraw, 0);
+
+    // the fees can be withdrawn again
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+    assert_eq!(contract.total_payment_withdraw, FEE / 5);
+}
+
+#[test]
+#[should_panic(expected = "Method resolve_withdrawal is private")]
+fn test_resolve_withdrawal_not_private() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env_with_promise_results(context(owner(), 0).build(), PromiseResult::Successful(vec![]));
+    contract.resolve_withdrawal(U128(FEE / 5), owner().into(), None);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NOTHING_TO_WITHDRAW")]
+fn test_withdraw_nothing() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+}
+
+#[test]
+fn test_withdraw_only_new_fees() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+
+    request(&mut contract, 2);
+    pay(&mut contract, 2);
+    confirm(&mut contract, 2);
+    claim(&mut contract, 2);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+
+    assert!(resolve_withdrawal(&mut contract, FEE / 5, owner(), None, PromiseResult::Successful(vec![])));
+    assert_eq!(get_logs(), vec![format!("Withdraw: amount {}", FEE / 5)]);
+    assert_eq!(contract.total_payment_withdraw, 2 * FEE / 5);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NOTHING_TO_WITHDRAW")]
+fn test_withdraw_twice() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+}
+
+#[test]
+#[should_panic(expected = "ERR_NOT_OWNER")]
+fn test_withdraw_not_owner() {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.withdraw();
+}
+
+fn approvers() -> Vec<AccountId> {
+    vec![owner().into(), stranger().into(), accounts(4).into()]
+}
+
+/// A claimed payment whose fee of `FEE / 5` only 2 of `approvers()` can withdraw.
+fn setup_multisig() -> PaymentShop {
+    let mut contract = setup_confirmed();
+    claim(&mut contract, 1);
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_approvers(approvers(), 2);
+    contract
+}
+
+fn propose_withdrawal(contract: &mut PaymentShop, amount: Balance) -> u64 {
+    testing_env!(context(stranger(), 1).build());
+    contract.propose_withdrawal(U128(amount), accounts(4).into()).0
+}
+
+#[test]
+fn test_multisig_withdrawal() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+
+    let proposal = contract.get_proposal(U64(proposal_id)).unwrap();
+    assert_eq!(proposal.action, ProposalAction::Withdraw { amount: U128(FEE / 10), receiver: accounts(4).into() });
+    assert_eq!(proposal.approvals, vec![AccountId::from(stranger())]);
+    assert_eq!(contract.total_payment_withdraw, 0);
+
+    testing_env!(context(owner(), 1).build());
+    contract.approve_proposal(U64(proposal_id));
+
+    let (approver, receiver): (AccountId, AccountId) = (owner().into(), accounts(4).into());
+    assert_eq!(get_logs(), vec![format!("Approve proposal: proposal_id: 1, account: {}", approver)]);
+    assert!(contract.get_proposal(U64(proposal_id)).is_none());
+    assert_eq!(contract.total_payment_withdraw, FEE / 10);
+
+    assert!(resolve_withdrawal(&mut contract, FEE / 10, accounts(4), Some(proposal_id), PromiseResult::Successful(vec![])));
+    assert_eq!(get_logs(), vec![format!("Withdraw: amount {}, receiver: {}, proposal_id: 1", FEE / 10, receiver)]);
+    assert_eq!(contract.total_payment_withdraw, FEE / 10);
+}
+
+#[test]
+fn test_multisig_withdrawal_failed_transfer() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 5);
+
+    testing_env!(context(owner(), 1).build());
+    contract.approve_proposal(U64(proposal_id));
+    assert_eq!(contract.total_payment_withdraw, FEE / 5);
+
+    assert!(!resolve_withdrawal(&mut contract, FEE / 5, accounts(4), Some(proposal_id), PromiseResult::Failed));
+    let receiver: AccountId = accounts(4).into();
+    assert_eq!(get_logs(), vec![format!("Withdraw failed: amount {}, receiver: {}", FEE / 5, receiver)]);
+    assert_eq!(contract.total_payment_withdraw, 0);
+
+    // the whole amount can be proposed again
+    propose_withdrawal(&mut contract, FEE / 5);
+}
+
+#[test]
+#[should_panic(expected = "ERR_MULTISIG_REQUIRED")]
+fn test_withdraw_with_approvers() {
+    let mut contract = setup_multisig();
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+}
+
+#[test]
+#[should_panic(expected = "ERR_APPROVERS_SET")]
+fn test_set_approvers_twice() {
+    let mut contract = setup_multisig();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_approvers(vec![owner().into()], 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_APPROVERS")]
+fn test_set_approvers_duplicate() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_approvers(vec![owner().into(), owner().into()], 2);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NOT_APPROVER")]
+fn test_propose_not_approver() {
+    let mut contract = setup_multisig();
+
+    testing_env!(context(shop(), 1).build());
+    contract.propose_withdrawal(U128(FEE / 10), shop().into());
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_WITHDRAWAL")]
+fn test_propose_withdrawal_too_high() {
+    let mut contract = setup_multisig();
+    propose_withdrawal(&mut contract, FEE / 5 + 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ALREADY_APPROVED")]
+fn test_approve_twice() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+
+    testing_env!(context(stranger(), 1).build());
+    contract.approve_proposal(U64(proposal_id));
+}
+
+#[test]
+#[should_panic(expected = "ERR_PROPOSAL_EXPIRED")]
+fn test_approve_expired() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+
+    testing_env!(context(owner(), 1).block_timestamp(PROPOSAL_DURATION).build());
+    contract.approve_proposal(U64(proposal_id));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_WITHDRAWAL")]
+fn test_approve_after_fees_withdrawn() {
+    let mut contract = setup_multisig();
+    let first = propose_withdrawal(&mut contract, FEE / 5);
+    let second = propose_withdrawal(&mut contract, FEE / 5);
+
+    testing_env!(context(owner(), 1).build());
+    contract.approve_proposal(U64(first));
+    contract.approve_proposal(U64(second));
+}
+
+#[test]
+fn test_cancel_proposal() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+
+    testing_env!(context(stranger(), 1).build());
+    contract.cancel_proposal(U64(proposal_id));
+    assert!(contract.get_proposal(U64(proposal_id)).is_none());
+
+    // expired proposals can be cleaned up by anyone
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+    testing_env!(context(shop(), 1).block_timestamp(PROPOSAL_DURATION).build());
+    contract.cancel_proposal(U64(proposal_id));
+    assert!(contract.get_proposal(U64(proposal_id)).is_none());
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_cancel_proposal_by_other_approver() {
+    let mut contract = setup_multisig();
+    let proposal_id = propose_withdrawal(&mut contract, FEE / 10);
+
+    testing_env!(context(accounts(4), 1).build());
+    contract.cancel_proposal(U64(proposal_id));
+}
+
+#[test]
+fn test_propose_approvers() {
+    let mut contract = setup_multisig();
+
+    testing_env!(context(stranger(), 1).build());
+    let proposal_id = contract.propose_approvers(vec![accounts(4).into()], 1);
+    assert_eq!(contract.get_approvers().threshold, 2);
+
+    testing_env!(context(accounts(4), 1).build());
+    contract.approve_proposal(proposal_id);
+    assert_eq!(contract.get_approvers().approvers, vec![AccountId::from(accounts(4))]);
+    assert_eq!(contract.get_approvers().threshold, 1);
+
+    // with a threshold of one, the proposer's own approval executes it
+    testing_env!(context(accounts(4), 1).build());
+    contract.propose_withdrawal(U128(FEE / 5), accounts(4).into());
+    assert_eq!(contract.total_payment_withdraw, FEE / 5);
+}
+
+#[test]
+fn test_set_payment_fee() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_payment_fee(U128(50000));
+
+    assert_eq!(contract.get_payment_shop_info().payment_fee_percent.0, 50000);
+    assert_eq!(get_logs(), vec!["Set payment fee: 50000".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_PAYMENT_FEE")]
+fn test_set_payment_fee_zero() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_payment_fee(U128(0));
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_PAYMENT_FEE")]
+fn test_set_payment_fee_above_decimals() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_payment_fee(U128(DECIMALS as u128 + 1));
+}
+
+#[test]
+fn test_set_full_payment_fee() {
+    let mut contract = setup_confirmed();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_payment_fee(U128(DECIMALS as u128));
+    claim(&mut contract, 1);
+
+    assert_eq!(get_logs(), vec!["Shop claim: payment_id: 1, amount 0".to_string()]);
+    assert_eq!(contract.get_payment_shop_info().total_payment.0, FEE);
+}
+
+#[test]
+#[should_panic(expected = "ERR_NOT_OWNER")]
+fn test_set_payment_fee_not_owner() {
+    let mut contract = setup();
+
+    testing_env!(context(shop(), 1).build());
+    contract.set_payment_fee(U128(50000));
+}
+
+#[test]
+fn test_set_tip_fee() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_tip_fee(U128(DECIMALS as u128));
+
+    assert_eq!(contract.get_payment_shop_info().tip_fee_percent.0, DECIMALS as u128);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_TIP_FEE")]
+fn test_set_tip_fee_too_high() {
+    let mut contract = setup();
+
+    testing_env!(context(owner(), 1).build());
+    contract.set_tip_fee(U128(DECIMALS as u128 + 1));
+}
+
+#[test]
+fn test_get_payment_info_unknown() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    assert!(contract.get_payment_info(U128(0)).is_none());
+    assert!(contract.get_payment_info(U128(2)).is_none());
+    assert_eq!(contract.get_payid_from_orderid(U128(2)), None);
+}
+
+#[test]
+fn test_stats() {
+    let mut contract = setup_confirmed();
+    request(&mut contract, 2);
+
+    let shop_stats = contract.get_shop_stats(shop().into());
+    let count = |status: Status| shop_stats.by_status.iter().find(|counter| counter.status == status).unwrap().count;
+    assert_eq!(count(Status::REQUESTING), 1);
+    assert_eq!(count(Status::CONFIRMED), 1);
+    assert_eq!(count(Status::PAID), 0);
+
+    claim(&mut contract, 1);
+    assert_eq!(contract.get_platform_stats().total_fees.0, FEE / 5);
+    assert_eq!(contract.get_shop_stats(stranger().into()).total_fees.0, 0);
+}
+
+#[test]
+fn test_balance_breakdown_escrow() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+    pay(&mut contract, 1);
+
+    let escrow = |contract: &PaymentShop, status: Status| {
+        contract.get_balance_breakdown().escrow_by_status.into_iter().find(|escrow| escrow.status == status).unwrap().amount.0
+    };
+    assert_eq!(escrow(&contract, Status::PAID), FEE);
+
+    confirm(&mut contract, 1);
+    assert_eq!((escrow(&contract, Status::PAID), escrow(&contract, Status::CONFIRMED)), (0, FEE));
+
+    claim(&mut contract, 1);
+    let breakdown = contract.get_balance_breakdown();
+    assert_eq!((breakdown.escrow.0, breakdown.pending_fees.0), (0, FEE / 5));
+    assert_eq!(contract.get_shop_stats(shop().into()).by_status.iter().map(|counter| counter.escrow.0).sum::<Balance>(), 0);
+
+    testing_env!(context(owner(), 1).build());
+    contract.withdraw();
+    assert_eq!(contract.get_balance_breakdown().pending_fees.0, 0);
+}
+
+#[test]
+fn test_balance_breakdown_adds_up() {
+    let mut contract = setup_confirmed();
+
+    testing_env!(context(shop(), STORAGE_DEPOSIT).build());
+    contract.storage_deposit(None, None);
+
+    let breakdown = contract.get_balance_breakdown();
+    assert_eq!(breakdown.storage_available, contract.storage_balance_of(shop()).unwrap().available);
+    assert_eq!(breakdown.shortfall.0, 0);
+    assert_eq!(
+        breakdown.balance.0,
+        breakdown.escrow.0 + breakdown.pending_fees.0 + breakdown.storage_locked.0 + breakdown.storage_available.0 + breakdown.surplus.0
+    );
+
+    testing_env!(context(shop(), 1).build());
+    contract.storage_withdraw(None);
+    assert_eq!(contract.get_balance_breakdown().storage_available.0, 0);
+}
+
+#[test]
+fn test_get_payment_history() {
+    let mut contract = setup();
+    request(&mut contract, 1);
+
+    testing_env!(context(user(), FEE).block_timestamp(10).block_index(1).build());
+    contract.pay(U128(1), None);
+
+    testing_env!(context(user(), 1).block_timestamp(20).block_index(2).build());
+    contract.confirm(U128(1));
+
+    let history = contract.get_payment_history(U128(1)).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!((history[0].timestamp.0, history[0].block_height.0), (10, 1));
+    assert_eq!(history[0].actor, AccountId::from(user()));
+    assert_eq!((history[0].from.clone(), history[0].to.clone()), (Status::REQUESTING, Status::PAID));
+    assert_eq!((history[1].timestamp.0, history[1].block_height.0), (20, 2));
+    assert_eq!((history[1].from.clone(), history[1].to.clone()), (Status::PAID, Status::CONFIRMED));
+}
+
+#[test]
+fn test_refund_deposit() {
+    testing_env!(context(user(), 1_000).build());
+    refund_deposit(0);
+
+    testing_env!(context(user(), STORAGE_DEPOSIT).build());
+    refund_deposit(10);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INSUFFICIENT_STORAGE_DEPOSIT")]
+fn test_refund_deposit_too_low() {
+    testing_env!(context(user(), 0).build());
+    refund_deposit(1);
+}
+
+/// State as the first deployed version left it: pay_ids 1 to 3 of `shop()`, paid,
+/// confirmed and claimed.
+fn setup_v1() {
+    testing_env!(context(owner(), 0).build());
+    let mut old = PaymentShopV1 {
+        owner_id: owner().into(),
+        pay_id: 3,
+        payment_fee_percent: PAYMENT_FEE_PERCENT,
+        total_payment: FEE / 5,
+        total_payment_withdraw: 0,
+        payments: LookupMap::new(StorageKey::PayIdKey),
+        order_ids: UnorderedMap::new(b"m")
+    };
+    for (pay_id, status) in [(1, Status::PAID), (2, Status::CONFIRMED), (3, Status::CLAIMED)] {
+        old.payments.insert(&pay_id, &UpgradePayment::V1(PaymentV1 {
+            payment_id: pay_id,
+            order_id: pay_id * 10,
+            shop: shop().into(),
+            user: user().into(),
+            msg: "Hello".to_string(),
+            fee: FEE,
+            status
+        }));
+        old.order_ids.insert(&(pay_id * 10), &pay_id);
+    }
+    env::state_write(&old);
+}
+
+fn migrate() -> PaymentShop {
+    testing_env!(context(accounts(5), 0).build());
+    PaymentShop::migrate(U128(0))
+}
+
+fn migrate_batch(contract: &mut PaymentShop, limit: u64) -> bool {
+    testing_env!(context(owner(), 1).build());
+    contract.migrate_batch(limit)
+}
+
+#[test]
+fn test_migrate() {
+    setup_v1();
+    let mut contract = migrate();
+
+    let payment = contract.get_payment_info(U128(1)).unwrap();
+    assert_eq!((payment.user, payment.status, payment.fee), (Some(user().into()), Status::PAID, U128(FEE)));
+    assert_eq!(contract.order_ids.get(&20), Some(2));
+    assert_eq!(contract.get_platform_stats().total_fees.0, FEE / 5);
+
+    assert!(!migrate_batch(&mut contract, 2));
+    assert_eq!(get_logs(), vec!["Migrate: payments: 2, remaining 1"]);
+    assert!(migrate_batch(&mut contract, 10));
+    assert_eq!(get_logs(), vec!["Migrate: payments: 3, remaining 0"]);
+    assert!(contract.migration.is_none());
+
+    let shop_stats = contract.get_shop_stats(shop().into());
+    let count = |status: Status| shop_stats.by_status.iter().find(|counter| counter.status == status).unwrap().count;
+    assert_eq!((count(Status::PAID), count(Status::CONFIRMED), count(Status::CLAIMED)), (1, 1, 1));
+
+    let breakdown = contract.get_balance_breakdown();
+    assert_eq!((breakdown.escrow.0, breakdown.pending_fees.0, breakdown.shortfall.0), (2 * FEE, FEE / 5, 0));
+
+    // the confirmed payment is found among the shop's unclaimed ones
+    testing_env!(context(shop(), 1).build());
+    contract.claim_all(10, None);
+    assert_eq!((status(&contract, 1), status(&contract, 2)), (Status::PAID, Status::CLAIMED));
+    assert_eq!(contract.total_payment, 2 * FEE / 5);
+
+    confirm(&mut contract, 1);
+    assert_eq!(contract.get_payment_history(U128(1)).unwrap().len(), 1);
+}
+
+#[test]
+fn test_migrate_batch_partly() {
+    setup_v1();
+    let mut contract = migrate();
+    migrate_batch(&mut contract, 1);
+
+    // migrated payments can be acted on while the rest wait
+    confirm(&mut contract, 1);
+    assert_eq!(status(&contract, 1), Status::CONFIRMED);
+    assert_eq!(contract.get_shop_stats(shop().into()).by_status.iter().map(|counter| counter.count).sum::<u64>(), 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_MIGRATION_PENDING")]
+fn test_migrate_payment_pending() {
+    setup_v1();
+    let mut contract = migrate();
+    migrate_batch(&mut contract, 1);
+
+    testing_env!(context(shop(), 1).build());
+    contract.claim(U128(2));
+}
+
+#[test]
+#[should_panic(expected = "ERR_NO_MIGRATION")]
+fn test_migrate_batch_done() {
+    setup_v1();
+    let mut contract = migrate();
+    migrate_batch(&mut contract, 3);
+    migrate_batch(&mut contract, 1);
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_migrate_batch_not_owner() {
+    setup_v1();
+    let mut contract = migrate();
+
+    testing_env!(context(shop(), 1).build());
+    contract.migrate_batch(1);
+}
+
+#[test]
+fn test_migrate_total_withdraw() {
+    setup_v1();
+    testing_env!(context(accounts(5), 0).build());
+    let contract = PaymentShop::migrate(U128(FEE / 5));
+
+    // what the first version paid out is not counted as pending again
+    assert_eq!(contract.get_balance_breakdown().pending_fees.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "ERR_INVALID_TOTAL_WITHDRAW")]
+fn test_migrate_total_withdraw_above_fees() {
+    setup_v1();
+    testing_env!(context(accounts(5), 0).build());
+    PaymentShop::migrate(U128(FEE / 5 + 1));
+}
+
+#[test]
+#[should_panic(expected = "ERR_ACCESS_DENIED")]
+fn test_migrate_not_contract() {
+    setup_v1();
+
+    testing_env!(context(owner(), 0).build());
+    PaymentShop::migrate(U128(0));
+}
+
+#[test]
+#[should_panic(expected = "ERR_STATE_NOT_FOUND")]
+fn test_migrate_without_state() {
+    migrate();
+}
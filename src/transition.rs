@@ -0,0 +1,262 @@
+use crate::*;
+
+/// Something that moves a payment from one status to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Pay,
+    Ship,
+    Deliver,
+    Confirm,
+    /// Confirmation taken by the shop once the auto-confirm window has passed.
+    AutoConfirm,
+    Dispute,
+    Claim,
+    Refund,
+    Archive,
+}
+
+/// The roles an account holds on a payment.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Actor {
+    pub shop: bool,
+    pub user: bool,
+    /// The user, or whoever funded the payment on their behalf.
+    pub payer: bool,
+    pub owner: bool,
+}
+
+impl Actor {
+    pub fn resolve(payment: &Payment, account_id: &AccountId, owner_id: &AccountId) -> Self {
+        let user = payment.user.as_ref() == Some(account_id);
+        Actor {
+            shop: &payment.shop == account_id,
+            user,
+            payer: user || payment.payer.as_ref() == Some(account_id),
+            owner: owner_id == account_id,
+        }
+    }
+}
+
+/// One entry of a payment's status history.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatusChange {
+    pub timestamp: u64,
+    pub block_height: u64,
+    pub actor: AccountId,
+    pub from: Status,
+    pub to: Status,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatusChangeJson {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub timestamp: U64,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub block_height: U64,
+    pub actor: AccountId,
+    pub from: Status,
+    pub to: Status,
+}
+
+impl From<StatusChange> for StatusChangeJson {
+    fn from(change: StatusChange) -> Self {
+        StatusChangeJson {
+            timestamp: U64(change.timestamp),
+            block_height: U64(change.block_height),
+            actor: change.actor,
+            from: change.from,
+            to: change.to,
+        }
+    }
+}
+
+impl Status {
+    /// Where `action` takes a payment in this status, whoever takes it.
+    pub fn next(&self, action: Action) -> Option<Status> {
+        use Status::*;
+        match (self, action) {
+            (REQUESTING, Action::Pay) => Some(PAID),
+            (PAID, Action::Ship) => Some(SHIPPED),
+            (SHIPPED, Action::Deliver) => Some(DELIVERED),
+            (PAID | SHIPPED | DELIVERED | DISPUTED, Action::Confirm) => Some(CONFIRMED),
+            (PAID | SHIPPED | DELIVERED, Action::AutoConfirm) => Some(CONFIRMED),
+            (PAID | SHIPPED | DELIVERED, Action::Dispute) => Some(DISPUTED),
+            (CONFIRMED, Action::Claim) => Some(CLAIMED),
+            (PAID | SHIPPED | DELIVERED | DISPUTED, Action::Refund) => Some(REFUNDED),
+            (CLAIMED | REFUNDED, Action::Archive) => Some(ARCHIVED),
+            _ => None,
+        }
+    }
+
+    /// Whether `actor` may take `action` on a payment in this status.
+    pub fn permits(&self, action: Action, actor: Actor) -> bool {
+        match action {
+            Action::Pay => actor.payer,
+            Action::Ship | Action::Deliver | Action::AutoConfirm | Action::Claim | Action::Archive => actor.shop,
+            // a disputed payment can only be settled by the owner
+            Action::Confirm if *self == Status::DISPUTED => actor.owner,
+            Action::Confirm => actor.user || actor.owner,
+            Action::Dispute => actor.user,
+            Action::Refund => actor.shop || actor.owner,
+        }
+    }
+
+    /// The status `actor` moves a payment to by taking `action`.
+    pub fn transition(&self, action: Action, actor: Actor) -> Result<Status, ContractError> {
+        let next = self.next(action).ok_or(ContractError::InvalidStatus)?;
+        if !self.permits(action, actor) {
+            return Err(ContractError::AccessDenied);
+        }
+        Ok(next)
+    }
+}
+
+impl PaymentShop {
+    /// Applies `action` by `account_id` to `payment`, recording it in the payment's history
+    /// and the status counters. Returns the bytes the history entry takes, which callers
+    /// leave out of what they charge: the payment pays for its history out of what it
+    /// settles when claimed or refunded.
+    pub(crate) fn internal_transition(&mut self, payment: &mut Payment, action: Action, account_id: &AccountId) -> StorageUsage {
+        let actor = Actor::resolve(payment, account_id, &self.owner_id);
+        let status = payment.status.transition(action, actor).unwrap_or_else(|err| panic_with(err));
+
+        let change = StatusChange {
+            timestamp: env::block_timestamp(),
+            block_height: env::block_index(),
+            actor: account_id.clone(),
+            from: payment.status.clone(),
+            to: status.clone(),
+        };
+        let change_bytes = change.try_to_vec().unwrap().len() as StorageUsage;
+        payment.history.push(change);
+        self.internal_set_status(payment, status);
+
+        change_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Status::*;
+
+    const ACTIONS: [Action; 9] = [
+        Action::Pay,
+        Action::Ship,
+        Action::Deliver,
+        Action::Confirm,
+        Action::AutoConfirm,
+        Action::Dispute,
+        Action::Claim,
+        Action::Refund,
+        Action::Archive,
+    ];
+
+    fn statuses() -> Vec<Status> {
+        let mut statuses = Status::tracked();
+        statuses.push(Status::ARCHIVED);
+        statuses
+    }
+
+    fn actors() -> Vec<Actor> {
+        (0..16u8)
+            .map(|roles| Actor {
+                shop: roles & 1 != 0,
+                user: roles & 2 != 0,
+                payer: roles & 4 != 0,
+                owner: roles & 8 != 0,
+            })
+            .collect()
+    }
+
+    /// Every allowed transition, with the roles of which any one is enough.
+    const ALLOWED: &[(Status, Action, Status, &[&str])] = &[
+        (REQUESTING, Action::Pay, PAID, &["payer"]),
+        (PAID, Action::Ship, SHIPPED, &["shop"]),
+        (SHIPPED, Action::Deliver, DELIVERED, &["shop"]),
+        (PAID, Action::Confirm, CONFIRMED, &["user", "owner"]),
+        (SHIPPED, Action::Confirm, CONFIRMED, &["user", "owner"]),
+        (DELIVERED, Action::Confirm, CONFIRMED, &["user", "owner"]),
+        (DISPUTED, Action::Confirm, CONFIRMED, &["owner"]),
+        (PAID, Action::AutoConfirm, CONFIRMED, &["shop"]),
+        (SHIPPED, Action::AutoConfirm, CONFIRMED, &["shop"]),
+        (DELIVERED, Action::AutoConfirm, CONFIRMED, &["shop"]),
+        (PAID, Action::Dispute, DISPUTED, &["user"]),
+        (SHIPPED, Action::Dispute, DISPUTED, &["user"]),
+        (DELIVERED, Action::Dispute, DISPUTED, &["user"]),
+        (CONFIRMED, Action::Claim, CLAIMED, &["shop"]),
+        (PAID, Action::Refund, REFUNDED, &["shop", "owner"]),
+        (SHIPPED, Action::Refund, REFUNDED, &["shop", "owner"]),
+        (DELIVERED, Action::Refund, REFUNDED, &["shop", "owner"]),
+        (DISPUTED, Action::Refund, REFUNDED, &["shop", "owner"]),
+        (CLAIMED, Action::Archive, ARCHIVED, &["shop"]),
+        (REFUNDED, Action::Archive, ARCHIVED, &["shop"]),
+    ];
+
+    fn expected(from: &Status, action: Action) -> Option<(Status, &'static [&'static str])> {
+        ALLOWED
+            .iter()
+            .find(|(status, allowed_action, _, _)| status == from && *allowed_action == action)
+            .map(|(_, _, to, roles)| (to.clone(), *roles))
+    }
+
+    fn holds_any(actor: Actor, roles: &[&str]) -> bool {
+        roles.iter().any(|role| match *role {
+            "shop" => actor.shop,
+            "user" => actor.user,
+            "payer" => actor.payer,
+            "owner" => actor.owner,
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn test_transition_table() {
+        for from in statuses() {
+            for action in ACTIONS.iter().copied() {
+                for actor in actors() {
+                    let expected = match expected(&from, action) {
+                        None => Err(ContractError::InvalidStatus),
+                        Some((_, roles)) if !holds_any(actor, roles) => Err(ContractError::AccessDenied),
+                        Some((to, _)) => Ok(to),
+                    };
+                    assert_eq!(from.transition(action, actor), expected, "{:?} {:?} by {:?}", from, action, actor);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_status_before_access() {
+        assert_eq!(Status::CLAIMED.transition(Action::Refund, Actor::default()), Err(ContractError::InvalidStatus));
+        assert_eq!(Status::PAID.transition(Action::Refund, Actor::default()), Err(ContractError::AccessDenied));
+    }
+
+    #[test]
+    fn test_final_statuses() {
+        assert!(ACTIONS.iter().all(|action| Status::ARCHIVED.next(*action).is_none()));
+        for status in [Status::CLAIMED, Status::REFUNDED].iter() {
+            assert!(ACTIONS.iter().all(|action| *action == Action::Archive || status.next(*action).is_none()));
+        }
+    }
+
+    #[test]
+    fn test_resolve_actor() {
+        let payment = Payment {
+            shop: "shop".to_string(),
+            user: Some("alice".to_string()),
+            payer: Some("bob".to_string()),
+            ..Payment::default()
+        };
+        let owner_id = "owner".to_string();
+
+        assert_eq!(Actor::resolve(&payment, &"shop".to_string(), &owner_id), Actor { shop: true, ..Actor::default() });
+        assert_eq!(Actor::resolve(&payment, &"alice".to_string(), &owner_id), Actor { user: true, payer: true, ..Actor::default() });
+        assert_eq!(Actor::resolve(&payment, &"bob".to_string(), &owner_id), Actor { payer: true, ..Actor::default() });
+        assert_eq!(Actor::resolve(&payment, &"owner".to_string(), &owner_id), Actor { owner: true, ..Actor::default() });
+        assert_eq!(Actor::resolve(&payment, &"carol".to_string(), &owner_id), Actor::default());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::*;
+use crate::errors::*;
+use near_sdk::{ext_contract, Gas, PromiseResult};
+
+pub const MAX_APPROVERS: usize = 10;
+/// How long a proposal can collect approvals: 7 days.
+pub const PROPOSAL_DURATION: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+/// Left for `resolve_withdrawal`, which logs and at most rolls back one counter.
+pub const GAS_FOR_RESOLVE_WITHDRAWAL: Gas = 10_000_000_000_000;
+
+#[ext_contract(ext_self)]
+pub trait WithdrawalResolver {
+    fn resolve_withdrawal(&mut self, amount: U128, receiver: AccountId, proposal_id: Option<U64>) -> bool;
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub enum ProposalAction {
+    /// Transfers `amount` of the platform fees not withdrawn yet to `receiver`.
+    Withdraw {
+        #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+        amount: U128,
+        receiver: AccountId,
+    },
+    /// Replaces the approvers and how many of them a proposal needs.
+    SetApprovers { approvers: Vec<AccountId>, threshold: u64 },
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Proposal {
+    pub action: ProposalAction,
+    pub proposer: AccountId,
+    /// The proposer's approval included. Approvals of accounts that stopped being
+    /// approvers no longer count.
+    pub approvals: Vec<AccountId>,
+    pub expires_at: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalJson {
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub proposal_id: U64,
+    pub action: ProposalAction,
+    pub proposer: AccountId,
+    pub approvals: Vec<AccountId>,
+    #[cfg_attr(feature = "abi", schemars(with = "crate::abi::Decimal"))]
+    pub expires_at: U64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "abi", derive(schemars::JsonSchema))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ApproversJson {
+    /// Empty until the owner sets them, which leaves `withdraw` to the owner alone.
+    pub approvers: Vec<AccountId>,
+    pub threshold: u64,
+}
+
+#[near_bindgen]
+impl PaymentShop {
+    /// Sets the first approvers. Once set, fees are only withdrawn through proposals
+    /// and the approvers can only be replaced by one.
+    #[payable]
+    pub fn set_approvers(&mut self, approvers: Vec<AccountId>, threshold: u64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(account_id == self.owner_id, ContractError::NotOwner);
+        require(self.approvers.is_empty(), ContractError::ApproversSet);
+
+        self.internal_set_approvers(approvers, threshold);
+    }
+
+    #[payable]
+    pub fn propose_withdrawal(&mut self, amount: U128, receiver: AccountId) -> U64 {
+        assert_one_yocto();
+        require(env::is_valid_account_id(receiver.as_bytes()), ContractError::InvalidAccountId);
+        require(amount.0 > 0 && amount.0 <= self.total_payment - self.total_payment_withdraw, ContractError::InvalidWithdrawal);
+
+        let account_id = env::predecessor_account_id();
+        let proposal_id = self.internal_propose(&account_id, ProposalAction::Withdraw { amount, receiver: receiver.clone() });
+
+        let log_message = format!("Propose withdrawal: proposal_id: {}, account: {}, amount {}, receiver: {}", proposal_id, account_id, amount.0, receiver);
+        env::log(log_message.as_bytes());
+
+        self.internal_execute_if_approved(proposal_id);
+        U64(proposal_id)
+    }
+
+    #[payable]
+    pub fn propose_approvers(&mut self, approvers: Vec<AccountId>, threshold: u64) -> U64 {
+        assert_one_yocto();
+        assert_valid_approvers(&approvers, threshold);
+
+        let account_id = env::predecessor_account_id();
+        let proposal_id = self.internal_propose(&account_id, ProposalAction::SetApprovers { approvers: approvers.clone(), threshold });
+
+        let log_message = format!("Propose approvers: proposal_id: {}, account: {}, threshold: {}, approvers: {}", proposal_id, account_id, threshold, approvers.join(" "));
+        env::log(log_message.as_bytes());
+
+        self.internal_execute_if_approved(proposal_id);
+        U64(proposal_id)
+    }
+
+    /// Executes the proposal with this approval if it is the last one it needs.
+    #[payable]
+    pub fn approve_proposal(&mut self, proposal_id: U64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(self.approvers.contains(&account_id), ContractError::NotApprover);
+
+        let mut proposal = self.internal_get_proposal(proposal_id.0);
+        require(env::block_timestamp() < proposal.expires_at, ContractError::ProposalExpired);
+        require(!proposal.approvals.contains(&account_id), ContractError::AlreadyApproved);
+
+        proposal.approvals.push(account_id.clone());
+        self.proposals.insert(&proposal_id.0, &proposal);
+
+        let log_message = format!("Approve proposal: proposal_id: {}, account: {}", proposal_id.0, account_id);
+        env::log(log_message.as_bytes());
+
+        self.internal_execute_if_approved(proposal_id.0);
+    }
+
+    /// Cancels an open proposal as its proposer or the owner, or an expired one as anyone.
+    #[payable]
+    pub fn cancel_proposal(&mut self, proposal_id: U64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let proposal = self.internal_get_proposal(proposal_id.0);
+        let expired = env::block_timestamp() >= proposal.expires_at;
+        require(expired || account_id == proposal.proposer || account_id == self.owner_id, ContractError::AccessDenied);
+
+        self.proposals.remove(&proposal_id.0);
+
+        let log_message = format!("Cancel proposal: proposal_id: {}, account: {}", proposal_id.0, account_id);
+        env::log(log_message.as_bytes());
+    }
+
+    /// Logs a withdrawal once its transfer went through. A failed transfer gives the
+    /// amount back to the fees left to withdraw. Returns whether it went through.
+    #[private]
+    pub fn resolve_withdrawal(&mut self, amount: U128, receiver: AccountId, proposal_id: Option<U64>) -> bool {
+        let transferred = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let log_message = match (transferred, proposal_id) {
+            (true, Some(proposal_id)) => format!("Withdraw: amount {}, receiver: {}, proposal_id: {}", amount.0, receiver, proposal_id.0),
+            (true, None) => format!("Withdraw: amount {}", amount.0),
+            (false, _) => {
+                self.total_payment_withdraw -= amount.0;
+                format!("Withdraw failed: amount {}, receiver: {}", amount.0, receiver)
+            }
+        };
+        env::log(log_message.as_bytes());
+        transferred
+    }
+
+    pub fn get_proposal(&self, proposal_id: U64) -> Option<ProposalJson> {
+        self.proposals.get(&proposal_id.0).map(|proposal| ProposalJson {
+            proposal_id,
+            action: proposal.action,
+            proposer: proposal.proposer,
+            approvals: proposal.approvals,
+            expires_at: U64(proposal.expires_at)
+        })
+    }
+
+    pub fn get_approvers(&self) -> ApproversJson {
+        ApproversJson {
+            approvers: self.approvers.clone(),
+            threshold: self.approval_threshold
+        }
+    }
+}
+
+fn assert_valid_approvers(approvers: &[AccountId], threshold: u64) {
+    require(!approvers.is_empty() && approvers.len() <= MAX_APPROVERS, ContractError::InvalidApprovers);
+    require(threshold > 0 && threshold <= approvers.len() as u64, ContractError::InvalidApprovers);
+    for (index, approver) in approvers.iter().enumerate() {
+        require(env::is_valid_account_id(approver.as_bytes()), ContractError::InvalidAccountId);
+        require(!approvers[..index].contains(approver), ContractError::InvalidApprovers);
+    }
+}
+
+impl PaymentShop {
+    /// Counts `amount` as withdrawn and transfers it, `resolve_withdrawal` logs the outcome.
+    pub(crate) fn internal_withdraw(&mut self, amount: Balance, receiver: AccountId, proposal_id: Option<u64>) {
+        self.total_payment_withdraw += amount;
+        Promise::new(receiver.clone()).transfer(amount).then(ext_self::resolve_withdrawal(
+            U128(amount),
+            receiver,
+            proposal_id.map(U64),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_WITHDRAWAL,
+        ));
+    }
+
+    fn internal_set_approvers(&mut self, approvers: Vec<AccountId>, threshold: u64) {
+        assert_valid_approvers(&approvers, threshold);
+
+        let log_message = format!("Set approvers: threshold: {}, approvers: {}", threshold, approvers.join(" "));
+        self.approvers = approvers;
+        self.approval_threshold = threshold;
+        env::log(log_message.as_bytes());
+    }
+
+    fn internal_get_proposal(&self, proposal_id: u64) -> Proposal {
+        let proposal = self.proposals.get(&proposal_id);
+        require(proposal.is_some(), ContractError::ProposalNotFound);
+        proposal.unwrap()
+    }
+
+    /// Stores a proposal of `account_id`, approved by it, and returns its id.
+    fn internal_propose(&mut self, account_id: &AccountId, action: ProposalAction) -> u64 {
+        require(self.approvers.contains(account_id), ContractError::NotApprover);
+
+        self.proposal_id += 1;
+        self.proposals.insert(&self.proposal_id, &Proposal {
+            action,
+            proposer: account_id.clone(),
+            approvals: vec![account_id.clone()],
+            expires_at: env::block_timestamp() + PROPOSAL_DURATION
+        });
+        self.proposal_id
+    }
+
+    fn internal_execute_if_approved(&mut self, proposal_id: u64) {
+        let proposal = self.internal_get_proposal(proposal_id);
+        let approvals = proposal.approvals.iter().filter(|approver| self.approvers.contains(approver)).count() as u64;
+        if approvals < self.approval_threshold {
+            return;
+        }
+        self.proposals.remove(&proposal_id);
+
+        match proposal.action {
+            ProposalAction::Withdraw { amount, receiver } => {
+                // other withdrawals may have been executed since it was proposed
+                require(amount.0 <= self.total_payment - self.total_payment_withdraw, ContractError::InvalidWithdrawal);
+                self.internal_withdraw(amount.0, receiver, Some(proposal_id));
+            }
+            ProposalAction::SetApprovers { approvers, threshold } => {
+                self.internal_set_approvers(approvers, threshold);
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::*;
+use crate::errors::*;
+
+/// The contract state as the first deployed version stored it.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PaymentShopV1 {
+    pub owner_id: AccountId,
+    pub pay_id: u128,
+    pub payment_fee_percent: u128,
+    pub total_payment: u128,
+    pub total_payment_withdraw: u128,
+    pub payments: LookupMap<u128, UpgradePayment>,
+    pub order_ids: UnorderedMap<u128, u128>
+}
+
+/// How far `migrate_batch` got through the payments of the first deployed version.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Migration {
+    /// The next payment to count in the stats and shop indexes.
+    pub next_pay_id: u128,
+    /// The last payment that version stored.
+    pub last_pay_id: u128,
+}
+
+#[near_bindgen]
+impl PaymentShop {
+    /// Moves the state of the first deployed version to the current layout, called by
+    /// the contract account along with deploying the new code. Its payments are kept
+    /// as stored and converted when read; the stats and shop indexes that version did
+    /// not keep are filled in from them by `migrate_batch`, a page at a time so that
+    /// no call runs out of gas however many payments there are.
+    ///
+    /// That version overwrote `total_payment_withdraw` with the last withdrawal instead
+    /// of adding to it, so the total actually withdrawn, as summed from its withdrawal
+    /// logs, is passed in. It cannot be less than the stored value nor more than the
+    /// fees taken.
+    #[init(ignore_state)]
+    pub fn migrate(total_payment_withdraw: U128) -> Self {
+        require(env::predecessor_account_id() == env::current_account_id(), ContractError::AccessDenied);
+        let old = env::state_read::<PaymentShopV1>();
+        require(old.is_some(), ContractError::StateNotFound);
+        let old = old.unwrap();
+        require(
+            old.total_payment_withdraw <= total_payment_withdraw.0 && total_payment_withdraw.0 <= old.total_payment,
+            ContractError::InvalidTotalWithdraw
+        );
+
+        let mut contract = PaymentShop {
+            owner_id: old.owner_id,
+            pay_id: old.pay_id,
+            payment_fee_percent: old.payment_fee_percent,
+            total_payment: old.total_payment,
+            total_payment_withdraw: total_payment_withdraw.0,
+            tip_fee_percent: 0,
+            payments: old.payments,
+            order_ids: old.order_ids,
+            shop_payments: LookupMap::new(StorageKey::ShopPaymentsKey),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccountsKey),
+            shop_stats: LookupMap::new(StorageKey::ShopStatsKey),
+            platform_stats: PaymentStats::default(),
+            coupons: LookupMap::new(StorageKey::CouponsKey),
+            coupon_uses: LookupMap::new(StorageKey::CouponUsesKey),
+            storage_available: 0,
+            approvers: Vec::new(),
+            approval_threshold: 0,
+            proposal_id: 0,
+            proposals: LookupMap::new(StorageKey::ProposalsKey),
+            migration: None
+        };
+        if old.pay_id > 0 {
+            contract.migration = Some(Migration { next_pay_id: 1, last_pay_id: old.pay_id });
+        }
+        // fees were only counted in total, so no shop is credited with them
+        contract.platform_stats.total_fees = contract.total_payment;
+
+        contract
+    }
+
+    /// Counts up to `limit` more payments of the first deployed version in the stats
+    /// and shop indexes, for the owner or the contract account. Until all of them are,
+    /// the ones left cannot be acted on and the stats leave them out. Returns whether
+    /// the migration is complete.
+    #[payable]
+    pub fn migrate_batch(&mut self, limit: u64) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require(account_id == self.owner_id || account_id == env::current_account_id(), ContractError::AccessDenied);
+        require(self.migration.is_some(), ContractError::NoMigration);
+        let mut migration = self.migration.take().unwrap();
+
+        let end = std::cmp::min(migration.next_pay_id.saturating_add(limit.into()), migration.last_pay_id + 1);
+        // nothing was archived then, so every pay_id up to the last one is stored
+        for pay_id in migration.next_pay_id..end {
+            let payment = Payment::from(self.payments.get(&pay_id).unwrap());
+            self.internal_update_stats(&payment.shop, |stats| {
+                stats.record_status(None, &payment.status, payment.fee);
+                stats.record_escrow(&Status::REQUESTING, &payment.status, payment.amount());
+            });
+            if payment.status != Status::CLAIMED {
+                self.internal_add_shop_payment(&payment.shop, pay_id);
+            }
+        }
+        migration.next_pay_id = end;
+
+        let log_message = format!("Migrate: payments: {}, remaining {}", end - 1, migration.last_pay_id + 1 - end);
+        env::log(log_message.as_bytes());
+
+        if end <= migration.last_pay_id {
+            self.migration = Some(migration);
+        }
+        self.migration.is_none()
+    }
+}
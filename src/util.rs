@@ -1,20 +1,31 @@
 use crate::*;
 
 pub(crate) fn assert_at_least_one_yocto() {
-    assert!(env::attached_deposit() >= 1, "Required attached deposit of at least 1 yoctoNEAR")
+    require(env::attached_deposit() >= 1, ContractError::DepositAtLeastOneYocto)
 }
 
 pub(crate) fn assert_one_yocto() {
-    assert_eq!(env::attached_deposit(), 1, "Required attached deposit of exactly 1 yoctoNEAR")
+    require(env::attached_deposit() == 1, ContractError::DepositExactlyOneYocto)
 }
 
-pub(crate) fn refund_deposit(storage_used: u64) {
+pub(crate) fn assert_storage_deposit(deposit: Balance, storage_used: u64) -> Balance {
     let required_cost = env::storage_byte_cost() * Balance::from(storage_used);
-    let attached_deposit = env::attached_deposit();
 
-    assert!(attached_deposit >= required_cost, "Must attach {} yoctoNear to cover storage", required_cost);
+    require(deposit >= required_cost, ContractError::InsufficientStorageDeposit { required: required_cost });
+
+    required_cost
+}
+
+pub(crate) fn refund_deposit(storage_used: u64) {
+    refund_unspent_deposit(0, storage_used)
+}
+
+/// Refunds what is attached beyond `spent` and the cost of `storage_used`.
+pub(crate) fn refund_unspent_deposit(spent: Balance, storage_used: u64) {
+    let unspent_deposit = env::attached_deposit() - spent;
+    let required_cost = assert_storage_deposit(unspent_deposit, storage_used);
 
-    let refund = attached_deposit - required_cost;
+    let refund = unspent_deposit - required_cost;
 
     if refund > 0 {
         Promise::new(env::predecessor_account_id()).transfer(refund);
@@ -0,0 +1,122 @@
+//! Checks `abi/payment_shop.json` against the methods the contract exports. It reads
+//! the sources rather than the `abi` module so it runs without the `abi` feature;
+//! `test_abi_up_to_date` in turn keeps the JSON in line with `abi::methods`.
+
+use near_sdk::serde_json::{self, Value};
+use std::fs;
+use std::path::Path;
+
+/// A method as both sides describe it: name, kind, arguments with whether they are
+/// required, whether it returns anything and whether it takes a deposit.
+type Method = (String, String, Vec<(String, bool)>, bool, bool);
+
+/// Splits an argument list at the commas that are not inside generic arguments.
+fn split_args(args: &str) -> Vec<&str> {
+    let (mut depth, mut start, mut parts) = (0, 0, vec![]);
+    for (i, c) in args.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+fn parse_signature(line: &str, payable: bool) -> Method {
+    let line = line.trim().trim_start_matches("pub fn ");
+    let open = line.find('(').unwrap();
+    let close = line.rfind(')').unwrap();
+    let mut kind = "init";
+    let mut args = vec![];
+    for arg in split_args(&line[open + 1..close]) {
+        match arg {
+            "&self" => kind = "view",
+            "&mut self" => kind = "call",
+            _ => {
+                let (name, ty) = arg.split_once(':').unwrap();
+                args.push((name.trim().to_string(), !ty.trim().starts_with("Option<")));
+            }
+        }
+    }
+    // init methods return `Self`, which is not part of the interface
+    let returns = kind != "init" && line[close..].contains("->");
+    (line[..open].to_string(), kind.to_string(), args, returns, payable)
+}
+
+/// Public methods of the `#[near_bindgen]` impls in `src`, leaving out `#[private]`
+/// callbacks, which only the contract itself may call.
+fn exported_methods() -> Vec<Method> {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let mut methods = vec![];
+    for entry in fs::read_dir(src).unwrap() {
+        let source = fs::read_to_string(entry.unwrap().path()).unwrap().replace('\r', "");
+        let mut lines = source.lines();
+        while let Some(line) = lines.next() {
+            if line != "#[near_bindgen]" || lines.next() != Some("impl PaymentShop {") {
+                continue;
+            }
+            let (mut private, mut payable) = (false, false);
+            for line in lines.by_ref().take_while(|line| *line != "}") {
+                match line.trim_end() {
+                    "    #[private]" => private = true,
+                    "    #[payable]" => payable = true,
+                    line if line.starts_with("    pub fn ") => {
+                        if !private {
+                            methods.push(parse_signature(line, payable));
+                        }
+                        private = false;
+                        payable = false;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    methods.sort();
+    methods
+}
+
+fn abi_methods() -> Vec<Method> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("abi/payment_shop.json");
+    let abi: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    let mut methods: Vec<Method> = abi["methods"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|method| {
+            let args = method["args"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|arg| (arg["name"].as_str().unwrap().to_string(), arg["required"].as_bool().unwrap()))
+                .collect();
+            (
+                method["name"].as_str().unwrap().to_string(),
+                method["kind"].as_str().unwrap().to_string(),
+                args,
+                !method["returns"].is_null(),
+                method["deposit"] != "none",
+            )
+        })
+        .collect();
+    methods.sort();
+    methods
+}
+
+#[test]
+fn test_abi_lists_exported_methods() {
+    let exported = exported_methods();
+    let listed = abi_methods();
+    for method in &exported {
+        assert!(listed.contains(method), "{:?} is exported but not in abi/payment_shop.json", method);
+    }
+    for method in &listed {
+        assert!(exported.contains(method), "{:?} is in abi/payment_shop.json but not exported", method);
+    }
+}
@@ -0,0 +1,35 @@
+//! The contract wasm the sim tests deploy, built from the current sources the first time
+//! it is needed rather than read from `out/`, which only `build.sh` refreshes.
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+static WASM: OnceLock<Vec<u8>> = OnceLock::new();
+
+pub fn wasm() -> &'static [u8] {
+    WASM.get_or_init(|| {
+        let manifest = workspace_manifest();
+        // the running `cargo test` holds the lock on the usual target dir
+        let target_dir = manifest.parent().unwrap().join("target").join("sim");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--target", "wasm32-unknown-unknown", "--release", "--manifest-path"])
+            .arg(&manifest)
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .env("RUSTFLAGS", "-C link-arg=-s")
+            .status()
+            .expect("failed to run cargo");
+        assert!(status.success(), "failed to build the contract wasm");
+
+        std::fs::read(target_dir.join("wasm32-unknown-unknown/release/payment_shop_rust.wasm")).unwrap()
+    })
+}
+
+fn workspace_manifest() -> PathBuf {
+    let output = Command::new(env!("CARGO"))
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("failed to run cargo");
+    PathBuf::from(String::from_utf8(output.stdout).unwrap().trim())
+}
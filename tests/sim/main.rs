@@ -1,15 +1,17 @@
-use near_sdk::{serde_json::json, json_types::U128};
+use near_sdk::{serde_json::{json, Value}, json_types::U128};
 use near_sdk_sim::{init_simulator, UserAccount, DEFAULT_GAS, STORAGE_AMOUNT, to_yocto};
-use payment_shop_rust::{PaymentJson, PaymentShopJson, Status};
+use payment_shop_rust::{BalanceBreakdownJson, ContractError, CouponJson, LineItem, PaymentJson, PaymentShopJson, PaymentStatsJson, Status, StatusChangeJson, StorageBalance};
 use near_sdk_sim::transaction::{ExecutionStatus};
+use near_sdk_sim::ExecutionResult;
 
-near_sdk_sim::lazy_static_include::lazy_static_include_bytes!{
-    PAYMENT_SHOP_CONTRACT_WASM_FILE => "out/payment-shop-contract.wasm",
-}
+#[path = "../common/contract.rs"]
+mod contract;
 
 const PAYMENT_SHOP_CONTRACT_ID: &str = "payment_shop_contract";
 const FEE_CONTRACT_PERCENT: &str = "20000"; // 20%
 const BOD_FEE_AMOUNT: u128 = 10000000000000000000000000; // 10 NEAR
+/// The first deployed version, as `build.sh` wrote it to `out/` before any upgrade.
+const PAYMENT_SHOP_V1_WASM: &[u8] = include_bytes!("../res/payment_shop_v1.wasm");
 
 pub fn init() -> (UserAccount, UserAccount, UserAccount, UserAccount) {
     let root = init_simulator(None);
@@ -18,7 +20,7 @@ pub fn init() -> (UserAccount, UserAccount, UserAccount, UserAccount) {
 
     // Deploy and init
     let payment_shop_contract = root.deploy_and_init(
-        &PAYMENT_SHOP_CONTRACT_WASM_FILE,
+        contract::wasm(),
         PAYMENT_SHOP_CONTRACT_ID.to_string(),
         "new", 
         &json!({
@@ -32,6 +34,16 @@ pub fn init() -> (UserAccount, UserAccount, UserAccount, UserAccount) {
     (root, alice, bod, payment_shop_contract)
 }
 
+pub fn assert_failure(outcome: &ExecutionResult, contract_error: ContractError) {
+    // assert error type
+    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
+        println!("Excute error: {}", error.to_string());
+        assert!(error.to_string().contains(&contract_error.to_string()));
+    } else {
+        unreachable!()
+    }
+}
+
 #[test]
 pub fn test_req_payment() {
     let (root, alice, bod, payment_shop_contract) = init();
@@ -70,7 +82,7 @@ pub fn test_req_payment() {
     assert_eq!(payment_json.payment_id, U128(1));
     assert_eq!(payment_json.order_id, U128(1));
     assert_eq!(payment_json.shop, alice.account_id());
-    assert_eq!(payment_json.user, bod.account_id());
+    assert_eq!(payment_json.user, Some(bod.account_id()));
     assert_eq!(payment_json.msg, "Hello");
     assert_eq!(payment_json.fee, U128(10000000000000000000000000));
     assert_eq!(payment_json.status, Status::REQUESTING);
@@ -88,13 +100,7 @@ pub fn test_req_payment() {
         to_yocto("0.01") 
     );
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome_req.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Order ID is set"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome_req, ContractError::OrderIdExists);
 }
 
 #[test]
@@ -134,7 +140,7 @@ pub fn test_pay() {
 
     assert_eq!(payment_json.payment_id, U128(1));
     assert_eq!(payment_json.shop, alice.account_id());
-    assert_eq!(payment_json.user, bod.account_id());
+    assert_eq!(payment_json.user, Some(bod.account_id()));
     assert_eq!(payment_json.msg, "Hello");
     assert_eq!(payment_json.fee, U128(10000000000000000000000000));
     assert_eq!(payment_json.status, Status::PAID);
@@ -169,13 +175,7 @@ pub fn test_confirm() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Required FEE deposit of at least 10000000000000000000000000 yoctoNEAR"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::InsufficientFee { required: BOD_FEE_AMOUNT });
 
     outcome = alice.call(
         payment_shop_contract.account_id(), 
@@ -189,13 +189,7 @@ pub fn test_confirm() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Access deny"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::AccessDenied);
 
     bod.call(
         payment_shop_contract.account_id(), 
@@ -227,7 +221,7 @@ pub fn test_confirm() {
 
     assert_eq!(payment_json.payment_id, U128(1));
     assert_eq!(payment_json.shop, alice.account_id());
-    assert_eq!(payment_json.user, bod.account_id());
+    assert_eq!(payment_json.user, Some(bod.account_id()));
     assert_eq!(payment_json.msg, "Hello");
     assert_eq!(payment_json.fee, U128(10000000000000000000000000));
     assert_eq!(payment_json.status, Status::CONFIRMED);
@@ -282,13 +276,7 @@ pub fn test_claim() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Access deny"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::AccessDenied);
 
     outcome = alice.call(
         payment_shop_contract.account_id(), 
@@ -302,13 +290,7 @@ pub fn test_claim() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("ERR_PAYMENT_NOT_FOUND"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::PaymentNotFound);
 
     alice.call(
         payment_shop_contract.account_id(), 
@@ -320,7 +302,7 @@ pub fn test_claim() {
         1
     );
 
-    let mut payment_json: PaymentJson = root.view(
+    let payment_json: PaymentJson = root.view(
         payment_shop_contract.account_id(), 
         "get_payment_info", 
         &json!({
@@ -330,7 +312,7 @@ pub fn test_claim() {
 
     assert_eq!(payment_json.payment_id, U128(1));
     assert_eq!(payment_json.shop, alice.account_id());
-    assert_eq!(payment_json.user, bod.account_id());
+    assert_eq!(payment_json.user, Some(bod.account_id()));
     assert_eq!(payment_json.msg, "Hello");
     assert_eq!(payment_json.fee, U128(10000000000000000000000000));
     assert_eq!(payment_json.status, Status::CLAIMED);
@@ -346,14 +328,14 @@ pub fn test_claim() {
     assert_eq!(payment_shop_json.total_payment, U128(10000000000000000000000000 * 20000 / 100000));
 
 
-    payment_json = root.view(
+    let unknown_payment_json: Option<PaymentJson> = root.view(
         payment_shop_contract.account_id(), 
         "get_payment_info", 
         &json!({
             "pay_id": U128(10)
         }).to_string().as_bytes()
     ).unwrap_json();
-    println!("payment_json {:#?}", payment_json)
+    assert!(unknown_payment_json.is_none());
 
 }
 
@@ -373,13 +355,7 @@ pub fn test_set_payment_fee() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Not admin or owner"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::NotOwner);
 
     outcome = alice.call(
         payment_shop_contract.account_id(), 
@@ -393,13 +369,7 @@ pub fn test_set_payment_fee() {
 
     assert_eq!(outcome.promise_errors().len(), 1);
 
-    // assert error type
-    if let ExecutionStatus::Failure(error) = &outcome.promise_errors().remove(0).unwrap().outcome().status {
-        println!("Excute error: {}", error.to_string());
-        assert!(error.to_string().contains("Invalid payment fee"));
-    } else {
-        unreachable!()
-    }
+    assert_failure(&outcome, ContractError::InvalidPaymentFee);
 
     alice.call(
         payment_shop_contract.account_id(), 
@@ -420,4 +390,1409 @@ pub fn test_set_payment_fee() {
     assert_eq!(payment_shop_json.pay_id, U128(0));
     assert_eq!(payment_shop_json.payment_fee_percent, U128(30000));
 
-}
\ No newline at end of file
+}
+
+#[test]
+pub fn test_claim_many() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..4 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        );
+
+        bod.call(
+            payment_shop_contract.account_id(), 
+            "pay", 
+            &json!({
+                "pay_id": U128(order_id)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            BOD_FEE_AMOUNT
+        );
+
+        bod.call(
+            payment_shop_contract.account_id(), 
+            "confirm", 
+            &json!({
+                "pay_id": U128(order_id)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            1 
+        );
+    }
+
+    let outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "claim_many", 
+        &json!({
+            "pay_ids": [U128(1), U128(2)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    assert_eq!(outcome.promise_errors().len(), 1);
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim_many", 
+        &json!({
+            "pay_ids": [U128(1), U128(2)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim_all", 
+        &json!({
+            "limit": 10
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    for pay_id in 1..4 {
+        let payment_json: PaymentJson = root.view(
+            payment_shop_contract.account_id(), 
+            "get_payment_info", 
+            &json!({
+                "pay_id": U128(pay_id)
+            }).to_string().as_bytes()
+        ).unwrap_json();
+
+        assert_eq!(payment_json.status, Status::CLAIMED);
+    }
+
+    let payment_shop_json: PaymentShopJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_shop_info", 
+        &json!({}).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_shop_json.total_payment, U128(3 * BOD_FEE_AMOUNT * 20000 / 100000));
+}
+
+#[test]
+pub fn test_req_payments() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    let pay_ids: Vec<U128> = alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payments", 
+        &json!({
+            "requests": [
+                {
+                    "order_id": U128(1),
+                    "user_id": bod.account_id(),
+                    "msg": "Hello",
+                    "fee": U128(BOD_FEE_AMOUNT)
+                },
+                {
+                    "order_id": U128(2),
+                    "user_id": bod.account_id(),
+                    "msg": "World",
+                    "fee": U128(BOD_FEE_AMOUNT)
+                }
+            ]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.1") 
+    ).unwrap_json();
+
+    assert_eq!(pay_ids, vec![U128(1), U128(2)]);
+
+    let payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.order_id, U128(2));
+    assert_eq!(payment_json.msg, "World");
+    assert_eq!(payment_json.status, Status::REQUESTING);
+
+    let outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payments", 
+        &json!({
+            "requests": [
+                {
+                    "order_id": U128(3),
+                    "user_id": bod.account_id(),
+                    "msg": "Hello",
+                    "fee": U128(BOD_FEE_AMOUNT)
+                },
+                {
+                    "order_id": U128(1),
+                    "user_id": bod.account_id(),
+                    "msg": "Hello",
+                    "fee": U128(BOD_FEE_AMOUNT)
+                }
+            ]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.1") 
+    );
+
+    assert_failure(&outcome, ContractError::OrderIdExists);
+
+    // the whole batch is rolled back
+    let pay_id: Option<U128> = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payid_from_orderid", 
+        &json!({
+            "order_id": U128(3)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(pay_id, None);
+}
+
+#[test]
+pub fn test_pay_many() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        );
+    }
+
+    let outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "pay_many", 
+        &json!({
+            "pay_ids": [U128(1), U128(2)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT
+    );
+
+    assert_failure(&outcome, ContractError::InsufficientFee { required: 2 * BOD_FEE_AMOUNT });
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay_many", 
+        &json!({
+            "pay_ids": [U128(1), U128(2)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("25")
+    ).assert_success();
+
+    for pay_id in 1..3 {
+        let payment_json: PaymentJson = root.view(
+            payment_shop_contract.account_id(), 
+            "get_payment_info", 
+            &json!({
+                "pay_id": U128(pay_id)
+            }).to_string().as_bytes()
+        ).unwrap_json();
+
+        assert_eq!(payment_json.status, Status::PAID);
+    }
+
+    // the 5 NEAR over the total fee is refunded
+    let bod_balance = bod.account().unwrap().amount;
+    assert!(bod_balance > to_yocto("79") && bod_balance < to_yocto("80"));
+}
+
+#[test]
+pub fn test_pay_refunds_overpayment() {
+    let (_root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    );
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("15")
+    ).assert_success();
+
+    // the 5 NEAR over the fee is refunded
+    let bod_balance = bod.account().unwrap().amount;
+    assert!(bod_balance > to_yocto("89") && bod_balance < to_yocto("90"));
+}
+
+#[test]
+pub fn test_open_payment() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "msg": "Donation",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    ).assert_success();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(2),
+            "msg": "Public checkout",
+            "fee": U128(BOD_FEE_AMOUNT),
+            "max_uses": 1
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    ).assert_success();
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT + to_yocto("0.01")
+    ).assert_success();
+
+    let mut payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.user, Some(bod.account_id()));
+    assert_eq!(payment_json.status, Status::PAID);
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT + to_yocto("0.01")
+    ).assert_success();
+
+    payment_json = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.user, None);
+    assert_eq!(payment_json.uses, 1);
+    assert_eq!(payment_json.status, Status::REQUESTING);
+
+    payment_json = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(3)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.user, Some(bod.account_id()));
+    assert_eq!(payment_json.parent_id, Some(U128(2)));
+    assert_eq!(payment_json.status, Status::PAID);
+
+    let outcome = root.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT + to_yocto("0.01")
+    );
+
+    assert_failure(&outcome, ContractError::PaymentLinkUsedUp);
+}
+
+#[test]
+pub fn test_pay_for() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        );
+
+        root.call(
+            payment_shop_contract.account_id(), 
+            "pay_for", 
+            &json!({
+                "pay_id": U128(order_id)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            BOD_FEE_AMOUNT + to_yocto("0.01")
+        ).assert_success();
+    }
+
+    let mut payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.user, Some(bod.account_id()));
+    assert_eq!(payment_json.payer, Some(root.account_id()));
+    assert_eq!(payment_json.status, Status::PAID);
+
+    // the user, not the payer, confirms
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    let outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "refund", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    );
+
+    assert_failure(&outcome, ContractError::AccessDenied);
+
+    let root_balance = root.account().unwrap().amount;
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "refund", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    payment_json = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.status, Status::REFUNDED);
+    // the refund goes back to the payer, less the storage of the payment's history until it is archived
+    let root_refund = root.account().unwrap().amount - root_balance;
+    assert!(root_refund < BOD_FEE_AMOUNT && root_refund > BOD_FEE_AMOUNT - to_yocto("0.01"));
+}
+
+#[test]
+pub fn test_storage_deposit() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "storage_deposit", 
+        &json!({}).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("1") 
+    ).assert_success();
+
+    let storage_balance_before: StorageBalance = root.view(
+        payment_shop_contract.account_id(), 
+        "storage_balance_of", 
+        &json!({
+            "account_id": alice.account_id()
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(storage_balance_before.total, U128(to_yocto("1")));
+
+    // storage is paid from the storage balance, 1 yocto is enough
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    let storage_balance_after: StorageBalance = root.view(
+        payment_shop_contract.account_id(), 
+        "storage_balance_of", 
+        &json!({
+            "account_id": alice.account_id()
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert!(storage_balance_after.available.0 < storage_balance_before.available.0);
+
+    let storage_balance: StorageBalance = alice.call(
+        payment_shop_contract.account_id(), 
+        "storage_withdraw", 
+        &json!({}).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).unwrap_json();
+
+    assert_eq!(storage_balance.available, U128(0));
+    assert_eq!(storage_balance.total.0, storage_balance_after.total.0 - storage_balance_after.available.0);
+}
+
+#[test]
+pub fn test_archive_payments() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    );
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT
+    );
+
+    let outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "archive_payments", 
+        &json!({
+            "pay_ids": [U128(1)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    assert_failure(&outcome, ContractError::InvalidStatus);
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    );
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "archive_payments", 
+        &json!({
+            "pay_ids": [U128(1)]
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    let payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.payment_id, U128(1));
+    assert_eq!(payment_json.status, Status::ARCHIVED);
+
+    let pay_id: Option<U128> = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payid_from_orderid", 
+        &json!({
+            "order_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(pay_id, None);
+}
+
+#[test]
+pub fn test_stats() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        );
+
+        bod.call(
+            payment_shop_contract.account_id(), 
+            "pay", 
+            &json!({
+                "pay_id": U128(order_id)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            BOD_FEE_AMOUNT
+        );
+    }
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    );
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "refund", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    let shop_stats: PaymentStatsJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_shop_stats", 
+        &json!({
+            "shop": alice.account_id()
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    for counter in shop_stats.by_status.iter() {
+        match counter.status {
+            Status::CLAIMED | Status::REFUNDED => {
+                assert_eq!(counter.count, 1);
+                assert_eq!(counter.volume, U128(BOD_FEE_AMOUNT));
+            },
+            _ => assert_eq!(counter.count, 0)
+        }
+    }
+    assert_eq!(shop_stats.total_fees, U128(BOD_FEE_AMOUNT * 20000 / 100000));
+    assert_eq!(shop_stats.total_refunded, U128(BOD_FEE_AMOUNT));
+
+    let platform_stats: PaymentStatsJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_platform_stats", 
+        &json!({}).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(platform_stats.total_fees, shop_stats.total_fees);
+    assert_eq!(platform_stats.total_refunded, shop_stats.total_refunded);
+}
+
+#[test]
+pub fn test_req_payment_metadata() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    let outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Invoice",
+            "fee": U128(BOD_FEE_AMOUNT),
+            "metadata": {
+                "line_items": [
+                    { "sku": "coffee", "qty": 2, "unit_price": U128(BOD_FEE_AMOUNT / 4) }
+                ],
+                "currency": "NEAR"
+            }
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    );
+
+    assert_failure(&outcome, ContractError::LineItemsMismatch);
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Invoice",
+            "fee": U128(BOD_FEE_AMOUNT),
+            "metadata": {
+                "line_items": [
+                    { "sku": "coffee", "qty": 2, "unit_price": U128(BOD_FEE_AMOUNT / 4) },
+                    { "sku": "cake", "qty": 1, "unit_price": U128(BOD_FEE_AMOUNT / 4) }
+                ],
+                "currency": "NEAR",
+                "tax": U128(BOD_FEE_AMOUNT / 4),
+                "reference": "https://example.com/invoices/1"
+            }
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    ).assert_success();
+
+    let payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    let metadata = payment_json.metadata.unwrap();
+    assert_eq!(metadata.line_items.len(), 2);
+    assert_eq!(metadata.line_items[1], LineItem { sku: "cake".to_string(), qty: 1, unit_price: U128(BOD_FEE_AMOUNT / 4) });
+    assert_eq!(metadata.currency, Some("NEAR".to_string()));
+    assert_eq!(metadata.tax, Some(U128(BOD_FEE_AMOUNT / 4)));
+}
+
+#[test]
+pub fn test_pay_with_coupon() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "create_coupon", 
+        &json!({
+            "code": "SALE10",
+            "discount": { "Percent": "10000" }, // 10%
+            "max_uses": 1,
+            "per_user_limit": 1
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    ).assert_success();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        );
+    }
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay_with_coupon", 
+        &json!({
+            "pay_id": U128(1),
+            "code": "SALE10"
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("9.01")
+    ).assert_success();
+
+    let payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.status, Status::PAID);
+    assert_eq!(payment_json.discount, U128(to_yocto("1")));
+    assert_eq!(payment_json.coupon, Some("SALE10".to_string()));
+
+    let coupon_json: CouponJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_coupon", 
+        &json!({
+            "shop": alice.account_id(),
+            "code": "SALE10"
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(coupon_json.uses, 1);
+
+    let outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "pay_with_coupon", 
+        &json!({
+            "pay_id": U128(2),
+            "code": "SALE10"
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("9.01")
+    );
+
+    assert_failure(&outcome, ContractError::CouponUsedUp);
+}
+
+#[test]
+pub fn test_pay_with_tip() {
+    let (root, alice, bod, payment_shop_contract) = init();
+    let staff = root.create_user("staff".to_string(), to_yocto("1"));
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "set_tip_fee", 
+        &json!({
+            "tip_fee_percent": U128(10000)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Table 4",
+            "fee": U128(BOD_FEE_AMOUNT),
+            "tip_receiver": staff.account_id()
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    ).assert_success();
+
+    let outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1),
+            "tip": U128(to_yocto("1"))
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT
+    );
+
+    assert_failure(&outcome, ContractError::InsufficientFee { required: BOD_FEE_AMOUNT + to_yocto("1") });
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1),
+            "tip": U128(to_yocto("1"))
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT + to_yocto("1")
+    ).assert_success();
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    );
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    // the tip, less the 10% tip fee, goes to the staff account
+    assert_eq!(staff.account().unwrap().amount, to_yocto("1") + to_yocto("0.9"));
+
+    let payment_shop_json: PaymentShopJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_shop_info", 
+        &json!({}).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_shop_json.total_payment, U128(BOD_FEE_AMOUNT * 20000 / 100000 + to_yocto("0.1")));
+}
+
+#[test]
+pub fn test_auto_confirm() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id":U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT),
+                "auto_confirm_after": "1000000000" // 1 second
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        ).assert_success();
+
+        bod.call(
+            payment_shop_contract.account_id(), 
+            "pay", 
+            &json!({
+                "pay_id": U128(order_id)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            BOD_FEE_AMOUNT
+        ).assert_success();
+    }
+
+    let mut outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    assert_failure(&outcome, ContractError::InvalidStatus);
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "dispute", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    root.borrow_runtime_mut().produce_blocks(5).unwrap();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    let payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.status, Status::CLAIMED);
+
+    // a disputed payment is never confirmed implicitly
+    outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    assert_failure(&outcome, ContractError::InvalidStatus);
+}
+
+#[test]
+pub fn test_delivery_tracking() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    );
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT
+    );
+
+    let mut outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "mark_delivered", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+
+    assert_failure(&outcome, ContractError::InvalidStatus);
+
+    outcome = bod.call(
+        payment_shop_contract.account_id(), 
+        "mark_shipped", 
+        &json!({
+            "pay_id": U128(1),
+            "tracking_ref": "DHL-123456"
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01")
+    );
+
+    assert_failure(&outcome, ContractError::AccessDenied);
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "mark_shipped", 
+        &json!({
+            "pay_id": U128(1),
+            "tracking_ref": "DHL-123456"
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01")
+    ).assert_success();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "mark_delivered", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    let mut payment_json: PaymentJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.status, Status::DELIVERED);
+    assert_eq!(payment_json.tracking_ref, Some("DHL-123456".to_string()));
+    assert!(payment_json.shipped_at.0 > 0);
+    assert!(payment_json.delivered_at.0 >= payment_json.shipped_at.0);
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    payment_json = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_info", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert_eq!(payment_json.status, Status::CONFIRMED);
+}
+
+#[test]
+pub fn test_payment_history() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id":U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01") 
+    );
+
+    let mut history: Vec<StatusChangeJson> = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_history", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert!(history.is_empty());
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "pay", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        BOD_FEE_AMOUNT
+    ).assert_success();
+
+    bod.call(
+        payment_shop_contract.account_id(), 
+        "confirm", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "claim", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1 
+    ).assert_success();
+
+    history = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_history", 
+        &json!({
+            "pay_id": U128(1)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    let changes: Vec<(String, Status, Status)> = history
+        .iter()
+        .map(|change| (change.actor.clone(), change.from.clone(), change.to.clone()))
+        .collect();
+    assert_eq!(changes, vec![
+        (bod.account_id(), Status::REQUESTING, Status::PAID),
+        (bod.account_id(), Status::PAID, Status::CONFIRMED),
+        (alice.account_id(), Status::CONFIRMED, Status::CLAIMED),
+    ]);
+    assert!(history.windows(2).all(|pair| pair[0].block_height.0 < pair[1].block_height.0));
+    assert!(history.windows(2).all(|pair| pair[0].timestamp.0 <= pair[1].timestamp.0));
+
+    let unknown_history: Option<Vec<StatusChangeJson>> = root.view(
+        payment_shop_contract.account_id(), 
+        "get_payment_history", 
+        &json!({
+            "pay_id": U128(2)
+        }).to_string().as_bytes()
+    ).unwrap_json();
+
+    assert!(unknown_history.is_none());
+}
+
+#[test]
+pub fn test_withdraw_failed_transfer() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "req_payment", 
+        &json!({
+            "order_id": U128(1),
+            "user_id": bod.account_id(),
+            "msg": "Hello",
+            "fee": U128(BOD_FEE_AMOUNT)
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        to_yocto("0.01")
+    ).assert_success();
+    bod.call(payment_shop_contract.account_id(), "pay", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, BOD_FEE_AMOUNT).assert_success();
+    bod.call(payment_shop_contract.account_id(), "confirm", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, 1).assert_success();
+    alice.call(payment_shop_contract.account_id(), "claim", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, 1).assert_success();
+    alice.call(
+        payment_shop_contract.account_id(), 
+        "set_approvers", 
+        &json!({
+            "approvers": [alice.account_id()],
+            "threshold": 1
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    ).assert_success();
+
+    let fees = BOD_FEE_AMOUNT * 20000 / 100000;
+    let pending_fees = || -> u128 {
+        let breakdown: BalanceBreakdownJson = root.view(
+            payment_shop_contract.account_id(), 
+            "get_balance_breakdown", 
+            &json!({}).to_string().as_bytes()
+        ).unwrap_json();
+        breakdown.pending_fees.0
+    };
+    let logs = |outcome: &ExecutionResult| -> Vec<String> {
+        outcome.promise_results().into_iter().flatten().flat_map(|result| result.logs().clone()).collect()
+    };
+
+    // a proposal of a single approver executes right away, the receiver does not exist
+    let outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "propose_withdrawal", 
+        &json!({
+            "amount": U128(fees),
+            "receiver": "missing"
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+    assert_eq!(outcome.promise_errors().len(), 1);
+    assert!(logs(&outcome).contains(&format!("Withdraw failed: amount {}, receiver: missing", fees)));
+    assert!(!logs(&outcome).iter().any(|log| log.starts_with("Withdraw: ")));
+    assert_eq!(pending_fees(), fees);
+
+    let bod_balance = bod.account().unwrap().amount;
+    let outcome = alice.call(
+        payment_shop_contract.account_id(), 
+        "propose_withdrawal", 
+        &json!({
+            "amount": U128(fees),
+            "receiver": bod.account_id()
+        }).to_string().as_bytes(), 
+        DEFAULT_GAS,
+        1
+    );
+    outcome.assert_success();
+    assert!(logs(&outcome).contains(&format!("Withdraw: amount {}, receiver: {}, proposal_id: 2", fees, bod.account_id())));
+    assert_eq!(pending_fees(), 0);
+    assert_eq!(bod.account().unwrap().amount, bod_balance + fees);
+}
+
+#[test]
+pub fn test_upgrade_from_v1() {
+    let root = init_simulator(None);
+    let alice = root.create_user("alice".to_string(), to_yocto("100"));
+    let bod = root.create_user("bod".to_string(), to_yocto("100"));
+
+    let payment_shop_contract = root.deploy_and_init(
+        PAYMENT_SHOP_V1_WASM,
+        PAYMENT_SHOP_CONTRACT_ID.to_string(),
+        "new", 
+        &json!({
+            "owner_id": alice.account_id(),
+            "payment_fee_percent": FEE_CONTRACT_PERCENT
+        }).to_string().as_bytes(), 
+        STORAGE_AMOUNT,
+        DEFAULT_GAS
+    );
+    let call = |account: &UserAccount, method: &str, args: Value, deposit: u128| -> ExecutionResult {
+        let outcome = account.call(payment_shop_contract.account_id(), method, &args.to_string().as_bytes(), DEFAULT_GAS, deposit);
+        outcome.assert_success();
+        outcome
+    };
+
+    // that version wrote every change to the latest payment, so each one is taken as
+    // far as it goes before the next is requested: claimed, confirmed and paid
+    for (pay_id, steps) in [(1, 3), (2, 2), (3, 1)] {
+        call(&alice, "req_payment", json!({ "order_id": U128(pay_id), "user_id": bod.account_id(), "msg": "Hello", "fee": U128(BOD_FEE_AMOUNT) }), to_yocto("0.01"));
+        call(&bod, "pay", json!({ "pay_id": U128(pay_id) }), BOD_FEE_AMOUNT);
+        if steps > 1 {
+            call(&bod, "confirm", json!({ "pay_id": U128(pay_id) }), 1);
+        }
+        if steps > 2 {
+            call(&alice, "claim", json!({ "pay_id": U128(pay_id) }), 1);
+        }
+    }
+    // the second withdrawal sends nothing but overwrites the total withdrawn with 0
+    let fees = BOD_FEE_AMOUNT * 20000 / 100000;
+    call(&alice, "withdraw", json!({}), 1);
+    call(&alice, "withdraw", json!({}), 1);
+
+    payment_shop_contract
+        .create_transaction(payment_shop_contract.account_id())
+        .deploy_contract(contract::wasm().to_vec())
+        .function_call("migrate".to_string(), json!({ "total_payment_withdraw": U128(fees) }).to_string().into_bytes(), DEFAULT_GAS, 0)
+        .submit()
+        .assert_success();
+
+    // payments wait for their batch before they can be acted on
+    let outcome = alice.call(payment_shop_contract.account_id(), "claim", &json!({ "pay_id": U128(2) }).to_string().as_bytes(), DEFAULT_GAS, 1);
+    assert_failure(&outcome, ContractError::MigrationPending);
+    assert!(!call(&alice, "migrate_batch", json!({ "limit": 2 }), 1).unwrap_json::<bool>());
+    assert!(call(&alice, "migrate_batch", json!({ "limit": 2 }), 1).unwrap_json::<bool>());
+
+    for (pay_id, status) in [(1, Status::CLAIMED), (2, Status::CONFIRMED), (3, Status::PAID)] {
+        let payment: Option<PaymentJson> = root.view(
+            payment_shop_contract.account_id(), 
+            "get_payment_info", 
+            &json!({ "pay_id": U128(pay_id) }).to_string().as_bytes()
+        ).unwrap_json();
+        let payment = payment.unwrap();
+        assert_eq!((payment.status, payment.user, payment.fee), (status, Some(bod.account_id()), U128(BOD_FEE_AMOUNT)));
+    }
+
+    let shop_stats: PaymentStatsJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_shop_stats", 
+        &json!({ "shop": alice.account_id() }).to_string().as_bytes()
+    ).unwrap_json();
+    for counter in shop_stats.by_status.iter() {
+        match counter.status {
+            Status::CLAIMED | Status::CONFIRMED | Status::PAID => assert_eq!(counter.count, 1, "{:?}", counter.status),
+            _ => assert_eq!(counter.count, 0, "{:?}", counter.status)
+        }
+    }
+
+    // what was withdrawn before is not pending again
+    let breakdown: BalanceBreakdownJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_balance_breakdown", 
+        &json!({}).to_string().as_bytes()
+    ).unwrap_json();
+    assert_eq!((breakdown.escrow.0, breakdown.pending_fees.0, breakdown.shortfall.0), (2 * BOD_FEE_AMOUNT, 0, 0));
+    let outcome = alice.call(payment_shop_contract.account_id(), "withdraw", &json!({}).to_string().as_bytes(), DEFAULT_GAS, 1);
+    assert_failure(&outcome, ContractError::NothingToWithdraw);
+
+    // the migrated payments carry on under the new code
+    call(&alice, "claim", json!({ "pay_id": U128(2) }), 1);
+    call(&bod, "confirm", json!({ "pay_id": U128(3) }), 1);
+    let platform_stats: PaymentStatsJson = root.view(
+        payment_shop_contract.account_id(), 
+        "get_platform_stats", 
+        &json!({}).to_string().as_bytes()
+    ).unwrap_json();
+    assert_eq!(platform_stats.total_fees, U128(2 * fees));
+}
+
+#[test]
+pub fn test_pay_confirm_claim_older_payment() {
+    let (root, alice, bod, payment_shop_contract) = init();
+
+    for order_id in 1..3 {
+        alice.call(
+            payment_shop_contract.account_id(), 
+            "req_payment", 
+            &json!({
+                "order_id": U128(order_id),
+                "user_id": bod.account_id(),
+                "msg": "Hello",
+                "fee": U128(BOD_FEE_AMOUNT)
+            }).to_string().as_bytes(), 
+            DEFAULT_GAS,
+            to_yocto("0.01") 
+        ).assert_success();
+    }
+
+    // every call changes the payment it is given, not the last one requested
+    bod.call(payment_shop_contract.account_id(), "pay", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, BOD_FEE_AMOUNT).assert_success();
+    bod.call(payment_shop_contract.account_id(), "confirm", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, 1).assert_success();
+    let outcome = alice.call(payment_shop_contract.account_id(), "claim", &json!({ "pay_id": U128(1) }).to_string().as_bytes(), DEFAULT_GAS, 1);
+    outcome.assert_success();
+    assert!(outcome.logs().iter().any(|log| log.starts_with("Shop claim: payment_id: 1,")));
+
+    let status = |pay_id: u128| -> String {
+        let payment: near_sdk::serde_json::Value = root.view(
+            payment_shop_contract.account_id(), 
+            "get_payment_info", 
+            &json!({
+                "pay_id": U128(pay_id)
+            }).to_string().as_bytes()
+        ).unwrap_json();
+        payment["status"].as_str().unwrap().to_string()
+    };
+    assert_eq!((status(1), status(2)), ("CLAIMED".to_string(), "REQUESTING".to_string()));
+}